@@ -1,4 +1,5 @@
 from typing import List
+from contextlib import closing
 from functools import lru_cache
 import struct
 from pathlib import Path
@@ -11,7 +12,7 @@ from openai import OpenAI
 
 @lru_cache(maxsize=1)
 def get_db_connection():
-    """Create and return a database connection."""
+    """Return the one database connection the server shares, opened on first use."""
     conn = sqlite3.connect("/Users/taigaishida/workspace/bako/bako.db")
     conn.enable_load_extension(True)
     sqlite_vec.load(conn)
@@ -19,25 +20,44 @@ def get_db_connection():
     return conn
 
 
+def get_active_model(db):
+    """Return the (model, dimensions) whose vectors bako currently serves for search."""
+    with closing(db.execute(
+        "SELECT key, value FROM meta WHERE key IN ('active_embedding_model', 'active_embedding_dimensions')"
+    )) as cursor:
+        meta = dict(cursor.fetchall())
+    return meta.get("active_embedding_model", "text-embedding-3-small"), int(meta.get("active_embedding_dimensions", 512))
+
+
 def build_index():
-    db = get_db_connection()    
+    db = get_db_connection()
+    model, dimensions = get_active_model(db)
     db.execute("DROP TABLE IF EXISTS vec_items")
-    db.execute("CREATE VIRTUAL TABLE vec_items USING vec0(embedding float[512] distance_metric=cosine, file_id text)")
+    db.execute(f"CREATE VIRTUAL TABLE vec_items USING vec0(embedding float[{dimensions}] distance_metric=cosine, file_id text)")
     db.execute(
         """
         INSERT INTO vec_items(embedding, file_id)
         SELECT vec_f32(embedding), file_id
-        FROM embeddings;
-        """
+        FROM embeddings
+        WHERE model = ? AND dimensions = ?;
+        """,
+        [model, dimensions],
     )
 
 @lru_cache(maxsize=1)
 def create_oai_client():
     return OpenAI()
 
-def get_embedding(text, model="text-embedding-3-small"):
+# bako names on-device models "local/<name>"; only bako itself can run them.
+LOCAL_MODEL_PREFIX = "local/"
+
+def get_embedding(db, text):
+    """Embed text with bako's active model, which must be an OpenAI model."""
+    model, dimensions = get_active_model(db)
+    if model.startswith(LOCAL_MODEL_PREFIX):
+        raise ValueError(f"bako-server can't embed with {model}; only OpenAI models are supported")
     text = text.replace("\n", " ")
-    return create_oai_client().embeddings.create(input = [text], model=model, dimensions=512).data[0].embedding
+    return create_oai_client().embeddings.create(input = [text], model=model, dimensions=dimensions).data[0].embedding
 
 def serialize_f32(vector: List[float]) -> bytes:
     """serializes a list of floats into a compact "raw bytes" format"""
@@ -59,8 +79,8 @@ def who(name: str) -> str:
     """
 
     try:
-        embedding = get_embedding(name)
         db = get_db_connection()
+        embedding = get_embedding(db, name)
         rows = db.execute(
             """
             WITH results AS (
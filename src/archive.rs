@@ -0,0 +1,629 @@
+//! Portable snapshots of an index, written by `bako export` and merged into
+//! another database by `bako import`.
+//!
+//! An archive is an uncompressed tar file with these entries:
+//!
+//! - `manifest.json`: the format name and version, the exporting node, the
+//!   active model and how many files, chunks and vectors follow.
+//! - `files.jsonl`: one [`ArchivedFile`] per line, with the stored text.
+//! - `chunks.jsonl`: one [`ArchivedChunk`] per line.
+//! - `embeddings.jsonl`: one [`ArchivedEmbedding`] per line, pointing into
+//!   `vectors.bin`.
+//! - `vectors.bin`: every vector back to back as little-endian `f32`s.
+//!
+//! Only the local index is exported; files mirrored from other nodes stay
+//! with the node that owns them.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::db::{Database, Transaction};
+use crate::embeddings::EmbeddingModel;
+use crate::error::{BakoError, Result};
+use crate::file::FileSource;
+use crate::reembed;
+use crate::sync;
+
+/// Identifies bako archives in the manifest.
+pub const ARCHIVE_FORMAT: &str = "bako-export";
+/// Bumped whenever the archive layout changes incompatibly.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const MANIFEST: &str = "manifest.json";
+const FILES: &str = "files.jsonl";
+const CHUNKS: &str = "chunks.jsonl";
+const EMBEDDINGS: &str = "embeddings.jsonl";
+const VECTORS: &str = "vectors.bin";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u32,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub node_id: String,
+    /// The model search used on the exporting node.
+    pub active_model: Option<ArchivedModel>,
+    /// Every model with vectors in the archive.
+    pub models: Vec<ModelVectors>,
+    pub files: usize,
+    pub chunks: usize,
+    pub vectors: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedModel {
+    pub model: String,
+    pub dimensions: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelVectors {
+    pub model: String,
+    pub dimensions: usize,
+    pub vectors: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedFile {
+    /// The file's id on the exporting node; chunks and embeddings refer to it.
+    pub file_id: String,
+    pub path: String,
+    pub file_type: String,
+    pub hash: String,
+    pub size: i64,
+    pub source: FileSource,
+    pub metadata: Option<serde_json::Value>,
+    pub updated_at: String,
+    /// The extracted text, absent when a privacy rule kept it out of the
+    /// database.
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedChunk {
+    pub file_id: String,
+    pub chunk_index: i64,
+    pub content: String,
+    pub token_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedEmbedding {
+    pub file_id: String,
+    /// `None` for vectors written before files were chunked.
+    pub chunk_index: Option<i64>,
+    pub model: String,
+    pub dimensions: usize,
+    /// Position of the vector's first value in `vectors.bin`, counted in
+    /// `f32`s; the vector is the `dimensions` values from there.
+    pub offset: usize,
+}
+
+/// What to do with an archived file whose path is already indexed with
+/// different content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keep the local file.
+    Skip,
+    /// Replace the local file with the archived one.
+    Replace,
+    /// Keep whichever was updated last.
+    Newer,
+}
+
+/// What [`export`] wrote.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportReport {
+    pub files: usize,
+    pub chunks: usize,
+    pub vectors: usize,
+}
+
+/// What [`import`] merged.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    /// Paths that weren't indexed here yet.
+    pub added: usize,
+    /// Local files overwritten because their content differed.
+    pub replaced: usize,
+    /// Files already indexed with the same hash.
+    pub unchanged: usize,
+    /// Local files kept despite differing from the archive.
+    pub kept: usize,
+    pub vectors: usize,
+    /// Imported files queued for embedding because the archive has no
+    /// vectors from this database's active model for them.
+    pub queued: usize,
+}
+
+fn invalid(entry: &str, e: impl std::fmt::Display) -> BakoError {
+    BakoError::Archive(format!("{}: {}", entry, e))
+}
+
+/// One archive entry, written next to the archive while the snapshot is
+/// read, since a tar header needs the entry's size before its data. Removed
+/// when dropped.
+struct Spool {
+    path: PathBuf,
+    out: BufWriter<std::fs::File>,
+    len: u64,
+}
+
+impl Spool {
+    fn create(dest: &Path, name: &str) -> Result<Spool> {
+        let path = dest.with_extension(format!("{}.partial", name));
+        let file = std::fs::File::create(&path).map_err(BakoError::io(&path))?;
+        Ok(Spool {
+            path,
+            out: BufWriter::new(file),
+            len: 0,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.out
+            .write_all(data)
+            .map_err(BakoError::io(&self.path))?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    fn json_line<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        // Serializing plain data into a Vec can't fail.
+        let mut line = serde_json::to_vec(value).expect("serializable archive entry");
+        line.push(b'\n');
+        self.write(&line)
+    }
+
+    fn append_to(mut self, builder: &mut tar::Builder<impl Write>, name: &str) -> std::io::Result<()> {
+        self.out.flush()?;
+        let data = std::fs::File::open(&self.path)?;
+        append(builder, name, self.len, BufReader::new(data))
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct Entries {
+    files: Spool,
+    chunks: Spool,
+    embeddings: Spool,
+    vectors: Spool,
+}
+
+impl Entries {
+    fn create(dest: &Path) -> Result<Entries> {
+        Ok(Entries {
+            files: Spool::create(dest, FILES)?,
+            chunks: Spool::create(dest, CHUNKS)?,
+            embeddings: Spool::create(dest, EMBEDDINGS)?,
+            vectors: Spool::create(dest, VECTORS)?,
+        })
+    }
+}
+
+/// Writes the local index to `entries` one file at a time and returns the
+/// manifest describing it.
+fn collect(tx: &Transaction, node_id: &str, entries: &mut Entries) -> Result<Manifest> {
+    let mut manifest = Manifest {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        created_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        node_id: node_id.to_string(),
+        active_model: reembed::active_model(tx)?.map(|m| ArchivedModel {
+            model: m.model,
+            dimensions: m.dimensions,
+        }),
+        models: Vec::new(),
+        files: 0,
+        chunks: 0,
+        vectors: 0,
+    };
+
+    for file in tx.files().list_files()? {
+        let content = tx.files().get_content(&file.id)?;
+        let snapshot = sync::snapshot_file(tx, file)?;
+        entries.files.json_line(&ArchivedFile {
+            file_id: snapshot.file_id.clone(),
+            path: snapshot.path,
+            file_type: snapshot.file_type,
+            hash: snapshot.hash,
+            size: snapshot.size,
+            source: snapshot.source,
+            metadata: snapshot.metadata,
+            updated_at: snapshot.updated_at,
+            content,
+        })?;
+        manifest.files += 1;
+
+        for chunk in snapshot.chunks {
+            entries.chunks.json_line(&ArchivedChunk {
+                file_id: snapshot.file_id.clone(),
+                chunk_index: chunk.chunk_index,
+                content: chunk.content,
+                token_count: chunk.token_count,
+            })?;
+            manifest.chunks += 1;
+        }
+
+        for embedding in snapshot.embeddings {
+            entries.embeddings.json_line(&ArchivedEmbedding {
+                file_id: snapshot.file_id.clone(),
+                chunk_index: embedding.chunk_index,
+                model: embedding.model.clone(),
+                dimensions: embedding.vector.len(),
+                offset: (entries.vectors.len / 4) as usize,
+            })?;
+            let bytes: Vec<u8> = embedding
+                .vector
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            entries.vectors.write(&bytes)?;
+            manifest.vectors += 1;
+            match manifest
+                .models
+                .iter_mut()
+                .find(|m| m.model == embedding.model && m.dimensions == embedding.vector.len())
+            {
+                Some(model) => model.vectors += 1,
+                None => manifest.models.push(ModelVectors {
+                    model: embedding.model,
+                    dimensions: embedding.vector.len(),
+                    vectors: 1,
+                }),
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+fn append(
+    builder: &mut tar::Builder<impl Write>,
+    name: &str,
+    size: u64,
+    data: impl Read,
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    );
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)
+}
+
+/// Writes every local file, with its chunks and vectors, to an archive at
+/// `dest`. The database is read from a single snapshot, so indexing can go
+/// on meanwhile. Entries are spooled to disk beside `dest` rather than held
+/// in memory, and the archive is written next to `dest` and renamed into
+/// place once complete.
+pub fn export(db: &Database, dest: &Path) -> Result<ExportReport> {
+    let mut entries = Entries::create(dest)?;
+    let manifest = db.snapshot(|tx| Ok(collect(tx, db.node_id(), &mut entries)))??;
+    let manifest_json = serde_json::to_vec_pretty(&manifest).expect("serializable archive manifest");
+
+    let partial = dest.with_extension("partial");
+    let write = || -> std::io::Result<()> {
+        let file = std::fs::File::create(&partial)?;
+        let mut builder = tar::Builder::new(BufWriter::new(file));
+        append(&mut builder, MANIFEST, manifest_json.len() as u64, &manifest_json[..])?;
+        entries.files.append_to(&mut builder, FILES)?;
+        entries.chunks.append_to(&mut builder, CHUNKS)?;
+        entries.embeddings.append_to(&mut builder, EMBEDDINGS)?;
+        entries.vectors.append_to(&mut builder, VECTORS)?;
+        let mut out = builder.into_inner()?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        std::fs::rename(&partial, dest)
+    };
+    if let Err(e) = write() {
+        let _ = std::fs::remove_file(&partial);
+        return Err(BakoError::io(dest)(e));
+    }
+
+    info!(
+        "Exported {} files, {} chunks and {} vectors to {}",
+        manifest.files,
+        manifest.chunks,
+        manifest.vectors,
+        dest.display()
+    );
+    Ok(ExportReport {
+        files: manifest.files,
+        chunks: manifest.chunks,
+        vectors: manifest.vectors,
+    })
+}
+
+/// An archive read into memory, with chunks and embeddings grouped by file.
+struct Archive {
+    files: Vec<ArchivedFile>,
+    chunks: HashMap<String, Vec<ArchivedChunk>>,
+    embeddings: HashMap<String, Vec<ArchivedEmbedding>>,
+    vectors: Vec<f32>,
+}
+
+fn parse_lines<T: serde::de::DeserializeOwned>(entry: &str, data: &[u8]) -> Result<Vec<T>> {
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|(number, line)| {
+            let line = line.map_err(|e| invalid(entry, e))?;
+            serde_json::from_str(&line)
+                .map_err(|e| invalid(entry, format!("line {}: {}", number + 1, e)))
+        })
+        .collect()
+}
+
+fn group_by_file<T>(items: Vec<T>, file_id: impl Fn(&T) -> &str) -> HashMap<String, Vec<T>> {
+    let mut grouped: HashMap<String, Vec<T>> = HashMap::new();
+    for item in items {
+        grouped.entry(file_id(&item).to_string()).or_default().push(item);
+    }
+    grouped
+}
+
+fn read_archive(src: &Path) -> Result<Archive> {
+    let file = std::fs::File::open(src).map_err(BakoError::io(src))?;
+    let mut entries: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut archive = tar::Archive::new(std::io::BufReader::new(file));
+    for entry in archive.entries().map_err(BakoError::io(src))? {
+        let mut entry = entry.map_err(BakoError::io(src))?;
+        let name = entry
+            .path()
+            .map_err(BakoError::io(src))?
+            .to_string_lossy()
+            .into_owned();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data).map_err(BakoError::io(src))?;
+        entries.insert(name, data);
+    }
+    let mut take = |name: &str| {
+        entries
+            .remove(name)
+            .ok_or_else(|| BakoError::Archive(format!("{} has no {}", src.display(), name)))
+    };
+
+    let manifest: Manifest =
+        serde_json::from_slice(&take(MANIFEST)?).map_err(|e| invalid(MANIFEST, e))?;
+    if manifest.format != ARCHIVE_FORMAT {
+        return Err(BakoError::Archive(format!(
+            "{} is not a bako archive",
+            src.display()
+        )));
+    }
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(BakoError::Archive(format!(
+            "{} is in format version {}; this bako reads up to {}",
+            src.display(),
+            manifest.version,
+            ARCHIVE_VERSION
+        )));
+    }
+
+    let files: Vec<ArchivedFile> = parse_lines(FILES, &take(FILES)?)?;
+    let chunks: Vec<ArchivedChunk> = parse_lines(CHUNKS, &take(CHUNKS)?)?;
+    let embeddings: Vec<ArchivedEmbedding> = parse_lines(EMBEDDINGS, &take(EMBEDDINGS)?)?;
+    let vectors = take(VECTORS)?;
+    if vectors.len() % 4 != 0 {
+        return Err(invalid(VECTORS, "length is not a multiple of 4 bytes"));
+    }
+    let vectors: Vec<f32> = vectors
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    if let Some(embedding) = embeddings
+        .iter()
+        .find(|e| e.offset.saturating_add(e.dimensions) > vectors.len())
+    {
+        return Err(invalid(
+            EMBEDDINGS,
+            format!(
+                "vector at {} with {} dimensions runs past the end of {}",
+                embedding.offset, embedding.dimensions, VECTORS
+            ),
+        ));
+    }
+
+    Ok(Archive {
+        files,
+        chunks: group_by_file(chunks, |c| &c.file_id),
+        embeddings: group_by_file(embeddings, |e| &e.file_id),
+        vectors,
+    })
+}
+
+/// Stores one archived file, replacing whatever is indexed under its path,
+/// and queues it for embedding if none of its vectors are from `active`.
+/// Returns how many vectors were written and whether it was queued.
+fn store_file(
+    tx: &Transaction,
+    archive: &Archive,
+    file: &ArchivedFile,
+    active: Option<&EmbeddingModel>,
+) -> rusqlite::Result<(usize, bool)> {
+    // The file need not exist on this machine, so it is never recorded as
+    // watched: garbage collection would remove it as deleted or outside the
+    // watched directory.
+    let source = match file.source {
+        FileSource::Watched => FileSource::Path,
+        source => source,
+    };
+    let stored = tx.files().upsert_file(
+        &file.path,
+        &file.file_type,
+        &file.hash,
+        file.size,
+        source,
+        file.metadata.as_ref(),
+    )?;
+    match &file.content {
+        Some(content) => tx.files().upsert_content(&stored.id, content)?,
+        None => tx.files().delete_content(&stored.id)?,
+    }
+
+    let mut chunks: Vec<&ArchivedChunk> = archive
+        .chunks
+        .get(&file.file_id)
+        .map(|chunks| chunks.iter().collect())
+        .unwrap_or_default();
+    chunks.sort_by_key(|chunk| chunk.chunk_index);
+    // Replacing the chunks also drops the local file's old vectors.
+    let chunk_ids = tx.chunks().replace_chunks(
+        &stored.id,
+        &chunks
+            .iter()
+            .map(|chunk| (chunk.content.clone(), chunk.token_count as usize))
+            .collect::<Vec<_>>(),
+    )?;
+    tx.embeddings().delete_for_file(&stored.id)?;
+
+    let mut vectors = 0;
+    let mut has_active = false;
+    for embedding in archive.embeddings.get(&file.file_id).into_iter().flatten() {
+        // Vectors from before chunking have nothing to attach to; the file
+        // is embedded again instead.
+        let Some(position) = embedding
+            .chunk_index
+            .and_then(|index| chunks.iter().position(|chunk| chunk.chunk_index == index))
+        else {
+            continue;
+        };
+        let vector = &archive.vectors[embedding.offset..embedding.offset + embedding.dimensions];
+        let json = serde_json::to_string(vector)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        tx.embeddings().insert_embedding(
+            &stored.id,
+            &chunk_ids[position],
+            &embedding.model,
+            embedding.dimensions,
+            &json,
+        )?;
+        vectors += 1;
+        has_active |= active.is_some_and(|active| {
+            active.model == embedding.model && active.dimensions == embedding.dimensions
+        });
+    }
+    if !has_active {
+        tx.jobs().insert_job(&stored.id)?;
+    }
+    Ok((vectors, !has_active))
+}
+
+/// Merges the archive at `src` into the database, in one transaction.
+/// Files are matched by path: new paths are added, paths indexed with the
+/// same hash are left alone and paths with different content are resolved
+/// with `on_conflict`. Imported files without vectors from the active model
+/// are queued for embedding from their stored text.
+pub fn import(db: &Database, src: &Path, on_conflict: ConflictPolicy) -> Result<ImportReport> {
+    let archive = read_archive(src)?;
+    let report = db.transaction(|tx| {
+        let active = reembed::active_model(tx)?;
+        let mut report = ImportReport::default();
+        for file in &archive.files {
+            match tx.files().find_by_path(&file.path)? {
+                Some(local) if local.hash == file.hash => {
+                    report.unchanged += 1;
+                    continue;
+                }
+                Some(local) => {
+                    let replace = match on_conflict {
+                        ConflictPolicy::Skip => false,
+                        ConflictPolicy::Replace => true,
+                        ConflictPolicy::Newer => file.updated_at > local.updated_at,
+                    };
+                    if !replace {
+                        report.kept += 1;
+                        continue;
+                    }
+                    report.replaced += 1;
+                }
+                None => report.added += 1,
+            }
+            let (vectors, queued) = store_file(tx, &archive, file, active.as_ref())?;
+            report.vectors += vectors;
+            report.queued += usize::from(queued);
+        }
+        Ok(report)
+    })?;
+    info!(
+        "Imported {}: {} added, {} replaced, {} unchanged, {} kept, {} vectors, {} queued for embedding",
+        src.display(),
+        report.added,
+        report.replaced,
+        report.unchanged,
+        report.kept,
+        report.vectors,
+        report.queued
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::temp_db;
+
+    fn add_file(db: &Database, path: &str, hash: &str, text: &str) {
+        db.transaction(|tx| {
+            let file = tx.files().upsert_file(path, "text/plain", hash, 5, FileSource::Watched, None)?;
+            tx.files().upsert_content(&file.id, text)?;
+            let chunk_ids = tx.chunks().replace_chunks(&file.id, &[(text.to_string(), 1)])?;
+            tx.embeddings()
+                .insert_embedding(&file.id, &chunk_ids[0], "m", 2, "[0.5,-1.0]")
+        })
+        .unwrap();
+    }
+
+    fn content(db: &Database, path: &str) -> Option<String> {
+        let file = db.files().unwrap().find_by_path(path).unwrap().unwrap();
+        db.files().unwrap().get_content(&file.id).unwrap()
+    }
+
+    #[test]
+    fn round_trips_and_resolves_conflicts_by_hash() {
+        let source = temp_db();
+        add_file(&source, "/notes/a.md", "h1", "alpha");
+        add_file(&source, "/notes/b.md", "h2", "beta");
+        let archive = std::env::temp_dir().join(format!("bako-export-{}.tar", uuid::Uuid::new_v4()));
+        let exported = export(&source, &archive).unwrap();
+        assert_eq!((exported.files, exported.chunks, exported.vectors), (2, 2, 2));
+        assert!(!archive.with_extension(format!("{}.partial", VECTORS)).exists());
+
+        let target = temp_db();
+        add_file(&target, "/notes/a.md", "h1", "alpha");
+        add_file(&target, "/notes/b.md", "local", "local beta");
+
+        let report = import(&target, &archive, ConflictPolicy::Skip).unwrap();
+        assert_eq!((report.added, report.unchanged, report.kept), (0, 1, 1));
+        assert_eq!(content(&target, "/notes/b.md").as_deref(), Some("local beta"));
+
+        let report = import(&target, &archive, ConflictPolicy::Replace).unwrap();
+        assert_eq!((report.replaced, report.vectors), (1, 1));
+        assert_eq!(content(&target, "/notes/b.md").as_deref(), Some("beta"));
+        let file = target.files().unwrap().find_by_path("/notes/b.md").unwrap().unwrap();
+        assert_eq!(file.source, FileSource::Path);
+        let vectors = target.embeddings().unwrap().get_for_file(&file.id).unwrap();
+        assert_eq!(vectors.len(), 1);
+        assert_eq!(vectors[0].embedding, "[0.5,-1.0]");
+
+        let _ = std::fs::remove_file(&archive);
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use bako::archive::ConflictPolicy;
+use bako::db::usage_repo::UsageGrouping;
+
+#[derive(Debug, Parser)]
+#[command(name = "bako", version, about = "Drag, drop, knowledge")]
+pub struct Cli {
+    /// Config file to use instead of the one in the user's config directory.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// What to do. Without a command, bako watches the configured directory.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Report embedding token usage and cost.
+    Usage {
+        /// Group totals by day, model or watched root.
+        #[arg(long, value_enum, default_value_t = UsageGrouping::Day)]
+        by: UsageGrouping,
+        /// Only include usage from this date (YYYY-MM-DD) onwards.
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Re-embed files with the configured embedding model and dimensions.
+    ///
+    /// Files are re-queued for the running bako to process in the background;
+    /// search keeps using the current vectors until every file is done.
+    Reembed,
+    /// Show index health: files, jobs, stale embeddings, provider and backlog ETA.
+    Status,
+    /// Index a file or directory once, without watching it. It may be
+    /// outside the watched directory.
+    Index {
+        path: PathBuf,
+        /// Embed the queued files now instead of leaving them to the running
+        /// bako.
+        #[arg(long)]
+        now: bool,
+    },
+    /// Index text under a name of your choosing, e.g. `notes://standup`.
+    IndexText {
+        /// Name the text is stored and found under.
+        #[arg(long)]
+        path: String,
+        /// Metadata stored with the text, as `key=value`. Repeatable.
+        #[arg(long = "meta", value_parser = parse_meta)]
+        metadata: Vec<(String, String)>,
+        /// The text to index. Read from stdin when omitted.
+        text: Option<String>,
+        /// Embed it now instead of leaving it to the running bako.
+        #[arg(long)]
+        now: bool,
+    },
+    /// Watch the configured directory and serve the HTTP API.
+    Serve {
+        /// Address to listen on. Defaults to `http_listen` from the config.
+        #[arg(long)]
+        listen: Option<String>,
+        /// Also accept changes pushed by other nodes.
+        #[arg(long)]
+        aggregator: bool,
+    },
+    /// Push changes to the aggregator in `[sync] push_url` now.
+    Push,
+    /// Mirror the peer in `[sync] pull_url` now.
+    Pull,
+    /// Write the index (files, text, chunks and vectors) to a portable
+    /// archive.
+    Export { dest: PathBuf },
+    /// Merge an archive written by `bako export` into this index.
+    Import {
+        src: PathBuf,
+        /// What to do with paths indexed here with different content.
+        #[arg(long, value_enum, default_value_t = ConflictPolicy::Skip)]
+        on_conflict: ConflictPolicy,
+    },
+    /// Back up, compact or check the database.
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Delete old completed jobs and files that left the watched directory.
+    Gc {
+        /// Only report what would be removed.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Find the indexed text most similar to a query.
+    Search {
+        query: String,
+        /// How many matches to show.
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// Copy the database while bako keeps running.
+    Backup {
+        /// File to write, or a directory to put a timestamped copy in.
+        dest: PathBuf,
+    },
+    /// Give back the space left by deleted files.
+    Vacuum,
+    /// Check the database file and look for orphaned rows.
+    Check {
+        /// Delete orphaned rows.
+        #[arg(long)]
+        repair: bool,
+    },
+}
+
+fn parse_meta(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value, got `{}`", s))
+}
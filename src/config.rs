@@ -1,8 +1,361 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
 use serde::{Deserialize, Serialize};
 use directories::BaseDirs;
 use tokio::fs;
 use tracing::{debug, error, info};
 
+use crate::error::{BakoError, Result};
+
+fn default_embedding_model() -> String {
+    "text-embedding-3-small".to_string()
+}
+
+fn default_embedding_dimensions() -> usize {
+    512
+}
+
+fn default_embedding_batch_size() -> usize {
+    256
+}
+
+fn default_embedding_batch_max_tokens() -> usize {
+    100_000
+}
+
+fn default_embedding_request_timeout_secs() -> u64 {
+    60
+}
+
+fn default_embedding_max_retries() -> u32 {
+    5
+}
+
+fn default_tokenizer() -> String {
+    "cl100k_base".to_string()
+}
+
+fn default_max_input_tokens() -> usize {
+    8191
+}
+
+fn default_http_listen() -> String {
+    "127.0.0.1:7733".to_string()
+}
+
+fn default_sync_interval_secs() -> u64 {
+    60
+}
+
+fn default_sync_batch_size() -> usize {
+    200
+}
+
+/// Replicating indexes between bakos: pushing this node's changes to a
+/// central aggregator, and mirroring a peer's index.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SyncConfig {
+    /// Base URL of a `bako serve --aggregator`, e.g. `http://central:7733`.
+    /// Nothing is pushed when unset.
+    #[serde(default)]
+    pub push_url: Option<String>,
+    /// Base URL of a peer's `bako serve` whose index is mirrored read-only.
+    /// Nothing is pulled when unset.
+    #[serde(default)]
+    pub pull_url: Option<String>,
+    #[serde(default = "default_sync_interval_secs")]
+    pub interval_secs: u64,
+    /// Change log entries covered by one push or pull request.
+    #[serde(default = "default_sync_batch_size")]
+    pub batch_size: usize,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            push_url: None,
+            pull_url: None,
+            interval_secs: default_sync_interval_secs(),
+            batch_size: default_sync_batch_size(),
+        }
+    }
+}
+
+fn default_backup_interval_hours() -> u64 {
+    24
+}
+
+fn default_backup_keep() -> usize {
+    7
+}
+
+/// Backups the running bako takes on its own.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BackupConfig {
+    /// Directory backups are written to. No scheduled backups when unset.
+    #[serde(default)]
+    pub directory: Option<String>,
+    #[serde(default = "default_backup_interval_hours")]
+    pub interval_hours: u64,
+    /// Scheduled backups kept in `directory`; older ones are deleted.
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            directory: None,
+            interval_hours: default_backup_interval_hours(),
+            keep: default_backup_keep(),
+        }
+    }
+}
+
+fn default_gc_interval_hours() -> u64 {
+    24
+}
+
+fn default_completed_job_retention_days() -> u32 {
+    30
+}
+
+/// Periodic cleanup of the database by the running bako.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GcConfig {
+    /// How often the running bako collects garbage; 0 turns it off.
+    #[serde(default = "default_gc_interval_hours")]
+    pub interval_hours: u64,
+    /// Jobs that completed more than this many days ago are deleted.
+    #[serde(default = "default_completed_job_retention_days")]
+    pub completed_job_retention_days: u32,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            interval_hours: default_gc_interval_hours(),
+            completed_job_retention_days: default_completed_job_retention_days(),
+        }
+    }
+}
+
+/// How log lines are laid out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Multi-line, human-friendly output.
+    #[default]
+    Pretty,
+    /// One line per event.
+    Compact,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Where log lines are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogDestination {
+    #[default]
+    Stdout,
+    Stderr,
+    /// Rotating files in `[logging] directory`.
+    File,
+}
+
+/// How often a new log file is started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+fn default_log_level() -> String {
+    format!("error,{}=info", crate::progress::LOG_TARGET)
+}
+
+fn default_log_max_files() -> usize {
+    7
+}
+
+/// Where bako logs to and how much.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub destination: LogDestination,
+    /// Default filter, e.g. `info` or `bako=debug,warn`. `RUST_LOG`
+    /// overrides it when set.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Directory log files are written to; defaults to `logs` in bako's data
+    /// directory.
+    #[serde(default)]
+    pub directory: Option<String>,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// Log files kept in `directory`; older ones are deleted on rotation.
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::default(),
+            destination: LogDestination::default(),
+            level: default_log_level(),
+            directory: None,
+            rotation: LogRotation::default(),
+            max_files: default_log_max_files(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// `directory`, or `logs` in bako's data directory.
+    pub fn log_directory(&self) -> Result<PathBuf> {
+        if let Some(dir) = &self.directory {
+            return Ok(PathBuf::from(dir));
+        }
+        let base_dirs = BaseDirs::new()
+            .ok_or_else(|| BakoError::Config("Couldn't find the base directory".to_string()))?;
+        Ok(base_dirs.data_dir().join("io.tonythetaiga.bako").join("logs"))
+    }
+}
+
+/// What to do with a file whose text is longer than the model accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizePolicy {
+    /// Embed only the first `max_input_tokens` tokens.
+    Truncate,
+    /// Embed the text as several chunks of at most `max_input_tokens` tokens.
+    #[default]
+    Split,
+    /// Don't embed the file and record why on its job.
+    Skip,
+}
+
+/// Where embeddings are computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingProvider {
+    /// The OpenAI embeddings API; needs `OPENAI_API_KEY` and network access.
+    #[default]
+    OpenAi,
+    /// A sentence-embedding model run on the CPU from `[local_model]`.
+    Local,
+}
+
+impl std::fmt::Display for EmbeddingProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            EmbeddingProvider::OpenAi => "openai",
+            EmbeddingProvider::Local => "local",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+fn default_local_batch_size() -> usize {
+    32
+}
+
+/// Settings for the on-device provider. `path` is a directory holding
+/// `config.json`, `tokenizer.json` and `model.safetensors` of a BERT-style
+/// sentence-embedding model such as all-MiniLM-L6-v2.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LocalModelConfig {
+    pub path: String,
+    /// Defaults to (and is capped at) the model's own input limit.
+    #[serde(default)]
+    pub max_input_tokens: Option<usize>,
+    /// Overrides the top-level `oversize_policy` for this provider.
+    #[serde(default)]
+    pub oversize_policy: Option<OversizePolicy>,
+    /// Inputs run through the model at once.
+    #[serde(default = "default_local_batch_size")]
+    pub batch_size: usize,
+}
+
+/// What to do with text matching a secret or PII pattern.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SensitiveAction {
+    /// Replace each match with `[REDACTED:<kind>]` and embed the rest.
+    #[default]
+    Redact,
+    /// Don't embed the file and record why on its job.
+    Skip,
+    /// Embed the text unchanged.
+    Allow,
+}
+
+/// Which providers may embed files matching a path rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathPolicy {
+    /// Any provider, including remote APIs.
+    #[default]
+    Remote,
+    /// Only the on-device provider.
+    Local,
+    /// Not embedded at all; its text isn't stored either.
+    Never,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PathRule {
+    /// Glob matched against the file's absolute path, e.g. `**/customers/**`.
+    pub pattern: String,
+    pub policy: PathPolicy,
+}
+
+fn default_path_rules() -> Vec<PathRule> {
+    ["**/.env", "**/.env.*", "**/*.pem", "**/*.key", "**/id_rsa*", "**/.ssh/**"]
+        .into_iter()
+        .map(|pattern| PathRule {
+            pattern: pattern.to_string(),
+            policy: PathPolicy::Never,
+        })
+        .collect()
+}
+
+/// Filtering applied to file text before it is embedded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PrivacyConfig {
+    /// Action for the built-in secret detectors (API keys, tokens, private keys).
+    #[serde(default)]
+    pub secrets: SensitiveAction,
+    /// Extra regular expressions treated as PII, e.g. customer IDs.
+    #[serde(default)]
+    pub pii_patterns: Vec<String>,
+    #[serde(default)]
+    pub pii: SensitiveAction,
+    /// Checked in order; the first matching rule applies. Files matching none
+    /// may go to any provider. Replaces the built-in rules when set.
+    #[serde(default = "default_path_rules")]
+    pub path_rules: Vec<PathRule>,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        PrivacyConfig {
+            secrets: SensitiveAction::default(),
+            pii_patterns: Vec::new(),
+            pii: SensitiveAction::default(),
+            path_rules: default_path_rules(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub db_path: String,
@@ -10,36 +363,145 @@ pub struct Config {
     pub watcher_poll_duration_secs: u64,
     pub queue_process_interval_secs: u64,
     pub queue_batch_size: usize,
+    #[serde(default)]
+    pub embedding_provider: EmbeddingProvider,
+    /// Embedding model to request. Changing it (or the dimensions) requires
+    /// `bako reembed` before search switches to the new vectors.
+    #[serde(default = "default_embedding_model")]
+    pub embedding_model: String,
+    #[serde(default = "default_embedding_dimensions")]
+    pub embedding_dimensions: usize,
+    /// Maximum number of inputs packed into a single embeddings request.
+    #[serde(default = "default_embedding_batch_size")]
+    pub embedding_batch_size: usize,
+    /// Upper bound on the estimated tokens sent in a single embeddings request.
+    #[serde(default = "default_embedding_batch_max_tokens")]
+    pub embedding_batch_max_tokens: usize,
+    /// Timeout applied to each embeddings HTTP request.
+    #[serde(default = "default_embedding_request_timeout_secs")]
+    pub embedding_request_timeout_secs: u64,
+    /// How many times a rate-limited or failed request is retried before the
+    /// jobs are left pending for the next queue run.
+    #[serde(default = "default_embedding_max_retries")]
+    pub embedding_max_retries: u32,
+    /// Tokenizer used to measure inputs: `cl100k_base`, `o200k_base` or
+    /// `approximate`.
+    #[serde(default = "default_tokenizer")]
+    pub tokenizer: String,
+    /// Largest input, in tokens, the embedding model accepts.
+    #[serde(default = "default_max_input_tokens")]
+    pub max_input_tokens: usize,
+    #[serde(default)]
+    pub oversize_policy: OversizePolicy,
+    /// Tokens that may be embedded per calendar month (UTC). Queue processing
+    /// pauses once the month's usage reaches it.
+    #[serde(default)]
+    pub monthly_token_budget: Option<u64>,
+    /// Price in USD per million tokens, keyed by model, used by `bako usage`.
+    #[serde(default)]
+    pub token_prices: HashMap<String, f64>,
+    #[serde(default)]
+    pub local_model: Option<LocalModelConfig>,
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    /// Address `bako serve` listens on for the HTTP API.
+    #[serde(default = "default_http_listen")]
+    pub http_listen: String,
+    /// Host names the HTTP API answers to besides `localhost` and IP
+    /// addresses, such as the name nodes push to. Requests naming any other
+    /// host are refused.
+    #[serde(default)]
+    pub http_allowed_hosts: Vec<String>,
+    /// Address plain `bako` serves `/metrics` on. `bako serve` always serves
+    /// it on `http_listen`.
+    #[serde(default)]
+    pub metrics_listen: Option<String>,
+    #[serde(default)]
+    pub sync: SyncConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub gc: GcConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 impl Config {
-    pub async fn load_or_init() -> Result<Self, Box<dyn std::error::Error>> {
-        let base_dirs = BaseDirs::new().ok_or("Couldn't find the base directory")?;
+    /// The watched root containing `path`, if any.
+    pub fn root_for(&self, path: &str) -> Option<&str> {
+        Path::new(path)
+            .starts_with(&self.watch_directory)
+            .then_some(self.watch_directory.as_str())
+    }
+
+    /// Rejects settings that parse but can't work.
+    fn validate(&self) -> Result<()> {
+        if self.sync.batch_size == 0 {
+            return Err(BakoError::Config(
+                "[sync] batch_size must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn load_or_init() -> Result<Self> {
+        let base_dirs = BaseDirs::new()
+            .ok_or_else(|| BakoError::Config("Couldn't find the base directory".to_string()))?;
         let bako_config_dir = base_dirs.config_dir().join("io.tonythetaiga.bako");
 
         if !bako_config_dir.exists() {
             info!("Creating config directory: {}", bako_config_dir.display());
-            std::fs::create_dir_all(&bako_config_dir)?;
+            std::fs::create_dir_all(&bako_config_dir).map_err(BakoError::io(&bako_config_dir))?;
         }
 
         let config_path: std::path::PathBuf = bako_config_dir.join("config.toml");
+        Self::load(&config_path).await
+    }
 
+    /// Reads the config file at `config_path`, e.g. one given with
+    /// `--config` to run several bakos side by side.
+    pub async fn load(config_path: &Path) -> Result<Self> {
         if config_path.exists() {
             info!("Reading existing config from {}", config_path.display());
-            let data = fs::read_to_string(&config_path).await.map_err(|e| {
+            let data = fs::read_to_string(config_path).await.map_err(|e| {
                 error!("Failed to read config file {}: {}", config_path.display(), e);
-                format!("Failed to read config file: {}", e)
+                BakoError::io(config_path)(e)
             })?;
 
             let cfg: Config = toml::from_str(&data).map_err(|e| {
                 error!("Failed to parse config file {}: {}", config_path.display(), e);
-                format!("Failed to parse config toml: {}", e)
+                BakoError::Config(format!("Failed to parse {}: {}", config_path.display(), e))
             })?;
 
+            cfg.validate().inspect_err(|e| {
+                error!("Invalid config file {}: {}", config_path.display(), e);
+            })?;
             debug!("Config loaded successfully: {:?}", cfg);
             Ok(cfg)
         } else {
-            Err("Config file does not exist.".into())
+            Err(BakoError::Config(format!(
+                "Config file {} does not exist",
+                config_path.display()
+            )))
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_batch_size_must_be_positive() {
+        let base = r#"
+            db_path = "bako.db"
+            watch_directory = "/tmp"
+            watcher_poll_duration_secs = 1
+            queue_process_interval_secs = 1
+            queue_batch_size = 10
+        "#;
+        let config: Config = toml::from_str(base).unwrap();
+        assert!(config.validate().is_ok());
+        let config: Config = toml::from_str(&format!("{}\n[sync]\nbatch_size = 0", base)).unwrap();
+        assert!(matches!(config.validate(), Err(BakoError::Config(_))));
+    }
+}
@@ -1,10 +1,20 @@
 use std::path::Path;
+use std::sync::Arc;
 
-use rusqlite::Connection;
+use rusqlite::{Connection, TransactionBehavior};
+use serde::Serialize;
+use uuid::Uuid;
 
+pub mod pool;
 pub mod job_repo;
 pub mod file_repo;
+pub mod chunk_repo;
 pub mod embedding_repo;
+pub mod usage_repo;
+pub mod meta_repo;
+pub mod change_log_repo;
+pub mod node_repo;
+pub mod integrity_repo;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FileEventType {
@@ -55,13 +65,13 @@ impl FileEvent {
 
         if let Some(event_type) = event_type {
             for path in event.paths {
-                if let Some(path_str) = path.to_str() {
-                    if path.is_file() || event_type == FileEventType::Delete {
-                        file_events.push(FileEvent {
-                            path: path_str.to_string(),
-                            event_type,
-                        });
-                    }
+                if let Some(path_str) = path.to_str()
+                    && (path.is_file() || event_type == FileEventType::Delete)
+                {
+                    file_events.push(FileEvent {
+                        path: path_str.to_string(),
+                        event_type,
+                    });
                 }
             }
         }
@@ -70,66 +80,501 @@ impl FileEvent {
     }
 }
 
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many
+/// have run; the first one is the original schema and is safe to re-run on
+/// databases created before versioning was introduced.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS files (
+        id TEXT PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        file_type TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE TRIGGER IF NOT EXISTS update_files_updated_at
+    AFTER UPDATE ON files
+    FOR EACH ROW
+    BEGIN
+        UPDATE files
+        SET updated_at = CURRENT_TIMESTAMP
+        WHERE id = OLD.id;
+    END;
+
+    CREATE TABLE IF NOT EXISTS jobs (
+        id TEXT PRIMARY KEY,
+        file_id TEXT NOT NULL,
+        status TEXT NOT NULL CHECK(status IN ('pending', 'running', 'completed', 'failed')),
+        error_message TEXT,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE IF NOT EXISTS embeddings (
+        id TEXT PRIMARY KEY,
+        file_id TEXT NOT NULL,
+        embedding TEXT NOT NULL,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
+    );
+    "#,
+    r#"
+    CREATE TABLE jobs_new (
+        id TEXT PRIMARY KEY,
+        file_id TEXT NOT NULL,
+        status TEXT NOT NULL CHECK(status IN ('pending', 'running', 'completed', 'failed', 'skipped')),
+        error_message TEXT,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
+    );
+    INSERT INTO jobs_new (id, file_id, status, error_message, created_at)
+        SELECT id, file_id, status, error_message, created_at FROM jobs;
+    DROP TABLE jobs;
+    ALTER TABLE jobs_new RENAME TO jobs;
+
+    CREATE TABLE chunks (
+        id TEXT PRIMARY KEY,
+        file_id TEXT NOT NULL,
+        chunk_index INTEGER NOT NULL,
+        content TEXT NOT NULL,
+        token_count INTEGER NOT NULL,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE (file_id, chunk_index),
+        FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
+    );
+
+    ALTER TABLE embeddings ADD COLUMN chunk_id TEXT REFERENCES chunks(id) ON DELETE CASCADE;
+    "#,
+    r#"
+    CREATE TABLE usage (
+        id TEXT PRIMARY KEY,
+        request_id TEXT NOT NULL,
+        provider TEXT NOT NULL,
+        model TEXT NOT NULL,
+        prompt_tokens INTEGER NOT NULL,
+        total_tokens INTEGER NOT NULL,
+        job_id TEXT,
+        root TEXT,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE INDEX usage_created_at ON usage (created_at);
+    "#,
+    r#"
+    ALTER TABLE embeddings ADD COLUMN model TEXT NOT NULL DEFAULT 'text-embedding-3-small';
+    ALTER TABLE embeddings ADD COLUMN dimensions INTEGER NOT NULL DEFAULT 512;
+
+    ALTER TABLE jobs ADD COLUMN kind TEXT NOT NULL DEFAULT 'index' CHECK(kind IN ('index', 'reembed'));
+
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+
+    -- Embeddings written so far all came from the previously hardcoded model.
+    INSERT INTO meta (key, value)
+        SELECT 'active_embedding_model', 'text-embedding-3-small' WHERE EXISTS (SELECT 1 FROM embeddings);
+    INSERT INTO meta (key, value)
+        SELECT 'active_embedding_dimensions', '512' WHERE EXISTS (SELECT 1 FROM embeddings);
+    "#,
+    r#"
+    CREATE TABLE file_contents (
+        file_id TEXT PRIMARY KEY,
+        content TEXT NOT NULL,
+        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
+    );
+    "#,
+    r#"
+    ALTER TABLE files ADD COLUMN source TEXT NOT NULL DEFAULT 'watched' CHECK(source IN ('watched', 'path', 'text'));
+    ALTER TABLE files ADD COLUMN metadata TEXT;
+    "#,
+    r#"
+    CREATE TABLE change_log (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        table_name TEXT NOT NULL CHECK(table_name IN ('files', 'embeddings')),
+        row_id TEXT NOT NULL,
+        file_id TEXT NOT NULL,
+        op TEXT NOT NULL CHECK(op IN ('insert', 'update', 'delete')),
+        changed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE TRIGGER files_log_insert AFTER INSERT ON files
+    BEGIN
+        INSERT INTO change_log (table_name, row_id, file_id, op) VALUES ('files', NEW.id, NEW.id, 'insert');
+    END;
+
+    -- Lists the columns so the updated_at bookkeeping update isn't logged twice.
+    CREATE TRIGGER files_log_update AFTER UPDATE OF path, file_type, hash, size, source, metadata ON files
+    BEGIN
+        INSERT INTO change_log (table_name, row_id, file_id, op) VALUES ('files', NEW.id, NEW.id, 'update');
+    END;
+
+    CREATE TRIGGER files_log_delete AFTER DELETE ON files
+    BEGIN
+        INSERT INTO change_log (table_name, row_id, file_id, op) VALUES ('files', OLD.id, OLD.id, 'delete');
+    END;
+
+    CREATE TRIGGER embeddings_log_insert AFTER INSERT ON embeddings
+    BEGIN
+        INSERT INTO change_log (table_name, row_id, file_id, op) VALUES ('embeddings', NEW.id, NEW.file_id, 'insert');
+    END;
+
+    CREATE TRIGGER embeddings_log_update AFTER UPDATE ON embeddings
+    BEGIN
+        INSERT INTO change_log (table_name, row_id, file_id, op) VALUES ('embeddings', NEW.id, NEW.file_id, 'update');
+    END;
+
+    CREATE TRIGGER embeddings_log_delete AFTER DELETE ON embeddings
+    BEGIN
+        INSERT INTO change_log (table_name, row_id, file_id, op) VALUES ('embeddings', OLD.id, OLD.file_id, 'delete');
+    END;
+
+    -- Rows written before the log existed count as inserted, so a replica
+    -- starting from zero sees everything.
+    INSERT INTO change_log (table_name, row_id, file_id, op) SELECT 'files', id, id, 'insert' FROM files;
+    INSERT INTO change_log (table_name, row_id, file_id, op) SELECT 'embeddings', id, file_id, 'insert' FROM embeddings;
+    "#,
+    r#"
+    CREATE TABLE sync_nodes (
+        node_id TEXT PRIMARY KEY,
+        last_seq INTEGER NOT NULL DEFAULT 0,
+        last_sync_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    );
+
+    CREATE TABLE node_files (
+        node_id TEXT NOT NULL,
+        file_id TEXT NOT NULL,
+        path TEXT NOT NULL,
+        file_type TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        metadata TEXT,
+        updated_at TIMESTAMP,
+        received_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (node_id, file_id),
+        FOREIGN KEY (node_id) REFERENCES sync_nodes(node_id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE node_chunks (
+        node_id TEXT NOT NULL,
+        file_id TEXT NOT NULL,
+        chunk_index INTEGER NOT NULL,
+        content TEXT NOT NULL,
+        token_count INTEGER NOT NULL,
+        PRIMARY KEY (node_id, file_id, chunk_index),
+        FOREIGN KEY (node_id, file_id) REFERENCES node_files(node_id, file_id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE node_embeddings (
+        node_id TEXT NOT NULL,
+        file_id TEXT NOT NULL,
+        chunk_index INTEGER,
+        model TEXT NOT NULL,
+        dimensions INTEGER NOT NULL,
+        embedding TEXT NOT NULL,
+        FOREIGN KEY (node_id, file_id) REFERENCES node_files(node_id, file_id) ON DELETE CASCADE
+    );
+
+    CREATE INDEX node_embeddings_file ON node_embeddings (node_id, file_id);
+    "#,
+    r#"
+    ALTER TABLE jobs ADD COLUMN finished_at TIMESTAMP;
+
+    CREATE INDEX jobs_finished_at ON jobs (finished_at);
+    "#,
+    r#"
+    ALTER TABLE jobs ADD COLUMN updated_at TIMESTAMP;
+    "#,
+    r#"
+    CREATE INDEX change_log_file_id ON change_log (file_id, seq);
+    "#,
+];
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Connections kept open per database. SQLite allows one writer at a time,
+/// so a few are enough to keep readers from waiting on it.
+const POOL_SIZE: usize = 4;
+
+/// Meta key holding this database's node id.
+const NODE_ID_KEY: &str = "node_id";
+
+/// Returns the id identifying this database to other bako nodes, generating
+/// it the first time.
+fn ensure_node_id(conn: &Connection) -> rusqlite::Result<String> {
+    let meta = meta_repo::MetaRepository::new(conn);
+    meta.set_if_absent(NODE_ID_KEY, &Uuid::new_v4().to_string())?;
+    meta.get(NODE_ID_KEY)
+        .map(|id| id.expect("node id was just stored"))
+}
+
+/// Pages copied per step of an online backup; the source is unlocked
+/// between steps so writers aren't held up for the whole copy.
+const BACKUP_PAGES_PER_STEP: std::os::raw::c_int = 1024;
+const BACKUP_STEP_PAUSE: std::time::Duration = std::time::Duration::from_millis(5);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DatabaseSize {
+    pub bytes: u64,
+    pub free_bytes: u64,
+}
+
+/// Cheap to clone: clones share the same connection pool.
+#[derive(Clone)]
 pub struct Database {
-    pub(crate) conn: Connection,
+    pool: Arc<pool::Pool>,
+    node_id: Arc<str>,
 }
 
 impl Database {
     pub fn new(path_str: &Path) -> rusqlite::Result<Database> {
-        let conn = Connection::open(path_str)?;
-        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
-        conn.execute_batch(
-            r#"
-            CREATE TABLE IF NOT EXISTS files (
-                id TEXT PRIMARY KEY,
-                path TEXT NOT NULL UNIQUE,
-                file_type TEXT NOT NULL,
-                hash TEXT NOT NULL,
-                size INTEGER NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            );
-
-            CREATE TRIGGER IF NOT EXISTS update_files_updated_at
-            AFTER UPDATE ON files
-            FOR EACH ROW
-            BEGIN
-                UPDATE files
-                SET updated_at = CURRENT_TIMESTAMP
-                WHERE id = OLD.id;
-            END;
-            
-            CREATE TABLE IF NOT EXISTS jobs (
-                id TEXT PRIMARY KEY,
-                file_id TEXT NOT NULL,
-                status TEXT NOT NULL CHECK(status IN ('pending', 'running', 'completed', 'failed')),
-                error_message TEXT,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
-            );
-
-            CREATE TABLE IF NOT EXISTS embeddings (
-                id TEXT PRIMARY KEY,
-                file_id TEXT NOT NULL,
-                embedding TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (file_id) REFERENCES files(id) ON DELETE CASCADE
-            );
-            "#,
-        )?;
-
-        Ok(Database { conn })
-    }
-
-    pub fn jobs(&self) -> job_repo::JobRepository {
-        job_repo::JobRepository::new(self)
-    }
-
-    pub fn files(&self) -> file_repo::FileRepository {
-        file_repo::FileRepository::new(self)
-    }
-
-    pub fn embeddings(&self) -> embedding_repo::EmbeddingRepository {
-        embedding_repo::EmbeddingRepository::new(self)
+        let mut conn = pool::open(path_str)?;
+        migrate(&mut conn)?;
+        let node_id = ensure_node_id(&conn)?;
+
+        let mut connections = vec![conn];
+        for _ in 1..POOL_SIZE {
+            connections.push(pool::open(path_str)?);
+        }
+        Ok(Database {
+            pool: Arc::new(pool::Pool::new(connections)),
+            node_id: node_id.into(),
+        })
+    }
+
+    /// Identifies this database when syncing with other bako nodes. Generated
+    /// on first open and kept for the life of the database.
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Runs `f` on Tokio's blocking thread pool, so SQLite's disk I/O (and
+    /// waiting for a pooled connection) never stalls the async runtime.
+    /// Panics in `f` are propagated to the caller.
+    pub async fn call<F, T>(&self, f: F) -> rusqlite::Result<T>
+    where
+        F: FnOnce(&Database) -> rusqlite::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.clone();
+        match tokio::task::spawn_blocking(move || f(&db)).await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            // Only happens while the runtime shuts down.
+            Err(e) => Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_INTERRUPT),
+                Some(e.to_string()),
+            )),
+        }
+    }
+
+    /// Runs `f` in a transaction on a single connection, committing when it
+    /// returns `Ok` and rolling back otherwise. The transaction takes the
+    /// write lock up front, so it never fails halfway for lack of it.
+    pub fn transaction<T>(
+        &self,
+        f: impl FnOnce(&Transaction) -> rusqlite::Result<T>,
+    ) -> rusqlite::Result<T> {
+        let conn = self.pool.get()?;
+        let tx = Transaction {
+            tx: rusqlite::Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?,
+        };
+        let result = f(&tx)?;
+        tx.tx.commit()?;
+        Ok(result)
+    }
+
+    /// Runs `f` in a read transaction: it sees the database as of its first
+    /// query and doesn't block writers, so long reads such as exports get a
+    /// consistent view. Always rolled back.
+    pub fn snapshot<T>(
+        &self,
+        f: impl FnOnce(&Transaction) -> rusqlite::Result<T>,
+    ) -> rusqlite::Result<T> {
+        let conn = self.pool.get()?;
+        let tx = Transaction {
+            tx: rusqlite::Transaction::new_unchecked(&conn, TransactionBehavior::Deferred)?,
+        };
+        f(&tx)
+    }
+
+    pub fn jobs(&self) -> rusqlite::Result<job_repo::JobRepository<pool::PooledConnection<'_>>> {
+        Ok(job_repo::JobRepository::new(self.pool.get()?))
+    }
+
+    pub fn files(&self) -> rusqlite::Result<file_repo::FileRepository<pool::PooledConnection<'_>>> {
+        Ok(file_repo::FileRepository::new(self.pool.get()?))
+    }
+
+    pub fn chunks(
+        &self,
+    ) -> rusqlite::Result<chunk_repo::ChunkRepository<pool::PooledConnection<'_>>> {
+        Ok(chunk_repo::ChunkRepository::new(self.pool.get()?))
+    }
+
+    pub fn embeddings(
+        &self,
+    ) -> rusqlite::Result<embedding_repo::EmbeddingRepository<pool::PooledConnection<'_>>> {
+        Ok(embedding_repo::EmbeddingRepository::new(self.pool.get()?))
+    }
+
+    pub fn usage(
+        &self,
+    ) -> rusqlite::Result<usage_repo::UsageRepository<pool::PooledConnection<'_>>> {
+        Ok(usage_repo::UsageRepository::new(self.pool.get()?))
+    }
+
+    pub fn meta(&self) -> rusqlite::Result<meta_repo::MetaRepository<pool::PooledConnection<'_>>> {
+        Ok(meta_repo::MetaRepository::new(self.pool.get()?))
+    }
+
+    pub fn change_log(
+        &self,
+    ) -> rusqlite::Result<change_log_repo::ChangeLogRepository<pool::PooledConnection<'_>>> {
+        Ok(change_log_repo::ChangeLogRepository::new(self.pool.get()?))
+    }
+
+    pub fn nodes(&self) -> rusqlite::Result<node_repo::NodeRepository<pool::PooledConnection<'_>>> {
+        Ok(node_repo::NodeRepository::new(self.pool.get()?))
+    }
+
+    pub fn integrity(
+        &self,
+    ) -> rusqlite::Result<integrity_repo::IntegrityRepository<pool::PooledConnection<'_>>> {
+        Ok(integrity_repo::IntegrityRepository::new(self.pool.get()?))
+    }
+
+    /// Copies the database to a new file at `dest` with SQLite's online
+    /// backup API. The copy is a consistent snapshot, including changes
+    /// still in the WAL; other connections keep working while it runs.
+    pub fn backup_to(&self, dest: &Path) -> rusqlite::Result<()> {
+        let conn = self.pool.get()?;
+        let mut copy = Connection::open(dest)?;
+        rusqlite::backup::Backup::new(&conn, &mut copy)?.run_to_completion(
+            BACKUP_PAGES_PER_STEP,
+            BACKUP_STEP_PAUSE,
+            None,
+        )
+    }
+
+    /// Rebuilds the database file without free pages, then truncates the
+    /// WAL. Waits for (and briefly blocks) writers.
+    pub fn vacuum(&self) -> rusqlite::Result<()> {
+        let conn = self.pool.get()?;
+        conn.execute_batch("VACUUM")?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+    }
+
+    /// The database's size in bytes and how much of it is free pages that
+    /// [`Database::vacuum`] would give back.
+    pub fn size(&self) -> rusqlite::Result<DatabaseSize> {
+        let conn = self.pool.get()?;
+        let pragma = |name: &str| conn.pragma_query_value(None, name, |row| row.get::<_, i64>(0));
+        let page_size = pragma("page_size")?;
+        Ok(DatabaseSize {
+            bytes: (pragma("page_count")? * page_size) as u64,
+            free_bytes: (pragma("freelist_count")? * page_size) as u64,
+        })
+    }
+
+    /// The current UTC time as SQLite's `datetime('now')` gives it, which
+    /// compares as a string with the timestamp columns.
+    pub fn now(&self) -> rusqlite::Result<String> {
+        self.pool
+            .get()?
+            .query_row("SELECT datetime('now')", [], |row| row.get(0))
+    }
+}
+
+/// An open transaction. Its repositories all use the transaction's
+/// connection, so their writes commit or roll back together.
+pub struct Transaction<'conn> {
+    tx: rusqlite::Transaction<'conn>,
+}
+
+impl Transaction<'_> {
+    pub fn jobs(&self) -> job_repo::JobRepository<&Connection> {
+        job_repo::JobRepository::new(&self.tx)
+    }
+
+    pub fn files(&self) -> file_repo::FileRepository<&Connection> {
+        file_repo::FileRepository::new(&self.tx)
+    }
+
+    pub fn chunks(&self) -> chunk_repo::ChunkRepository<&Connection> {
+        chunk_repo::ChunkRepository::new(&self.tx)
+    }
+
+    pub fn embeddings(&self) -> embedding_repo::EmbeddingRepository<&Connection> {
+        embedding_repo::EmbeddingRepository::new(&self.tx)
+    }
+
+    pub fn usage(&self) -> usage_repo::UsageRepository<&Connection> {
+        usage_repo::UsageRepository::new(&self.tx)
+    }
+
+    pub fn meta(&self) -> meta_repo::MetaRepository<&Connection> {
+        meta_repo::MetaRepository::new(&self.tx)
+    }
+
+    pub fn change_log(&self) -> change_log_repo::ChangeLogRepository<&Connection> {
+        change_log_repo::ChangeLogRepository::new(&self.tx)
+    }
+
+    pub fn nodes(&self) -> node_repo::NodeRepository<&Connection> {
+        node_repo::NodeRepository::new(&self.tx)
+    }
+
+    pub fn integrity(&self) -> integrity_repo::IntegrityRepository<&Connection> {
+        integrity_repo::IntegrityRepository::new(&self.tx)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Database;
+
+    /// A database in a temporary file, removed when dropped.
+    pub struct TempDb {
+        db: Database,
+        path: std::path::PathBuf,
+    }
+
+    impl std::ops::Deref for TempDb {
+        type Target = Database;
+
+        fn deref(&self) -> &Database {
+            &self.db
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+            }
+        }
+    }
+
+    pub fn temp_db() -> TempDb {
+        let path = std::env::temp_dir().join(format!("bako-test-{}.db", uuid::Uuid::new_v4()));
+        TempDb {
+            db: Database::new(&path).unwrap(),
+            path,
+        }
     }
 }
@@ -0,0 +1,236 @@
+use std::ops::Deref;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
+use rusqlite::{Connection, Result, Row, params};
+
+/// What happened to a logged row; stored as its lowercase name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl ChangeOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeOp::Insert => "insert",
+            ChangeOp::Update => "update",
+            ChangeOp::Delete => "delete",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Option<ChangeOp> {
+        match s {
+            "insert" => Some(ChangeOp::Insert),
+            "update" => Some(ChangeOp::Update),
+            "delete" => Some(ChangeOp::Delete),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ChangeOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromSql for ChangeOp {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        ChangeOp::from_string(s)
+            .ok_or_else(|| FromSqlError::Other(format!("Unknown change op: {}", s).into()))
+    }
+}
+
+/// One row of the change log. Triggers append an entry whenever a `files`
+/// or `embeddings` row is inserted, updated or deleted, so a sync layer can
+/// replay everything after the last `seq` it saw.
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// Increases with every change and is never reused.
+    pub seq: i64,
+    /// `files` or `embeddings`.
+    pub table_name: String,
+    pub row_id: String,
+    /// The file the row belongs to (the row itself for `files`), kept so
+    /// deletes can be applied after the row is gone.
+    pub file_id: String,
+    pub op: ChangeOp,
+    pub changed_at: String,
+}
+
+fn row_to_change(row: &Row) -> Result<Change> {
+    Ok(Change {
+        seq: row.get(0)?,
+        table_name: row.get(1)?,
+        row_id: row.get(2)?,
+        file_id: row.get(3)?,
+        op: row.get(4)?,
+        changed_at: row.get(5)?,
+    })
+}
+
+pub struct ChangeLogRepository<C> {
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection>> ChangeLogRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    /// Up to `limit` changes with a sequence number above `after`, oldest
+    /// first.
+    pub fn since(&self, after: i64, limit: usize) -> Result<Vec<Change>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT seq, table_name, row_id, file_id, op, changed_at FROM change_log WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+        )?;
+        let changes = stmt
+            .query_map(params![after, limit as i64], row_to_change)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(changes)
+    }
+
+    /// Deletes changes up to `up_to` that a later change to the same file
+    /// supersedes, returning how many (or, with `dry_run`, would be). Every
+    /// file keeps its latest change, so a receiver resuming from any cursor,
+    /// or starting over, still gets every file's current state.
+    pub fn prune_superseded(&self, up_to: i64, dry_run: bool) -> Result<usize> {
+        const SUPERSEDED: &str = "seq <= ?1 AND EXISTS (SELECT 1 FROM change_log later WHERE later.file_id = change_log.file_id AND later.seq > change_log.seq)";
+        if dry_run {
+            let count: i64 = self.conn.query_row(
+                &format!("SELECT COUNT(*) FROM change_log WHERE {}", SUPERSEDED),
+                [up_to],
+                |row| row.get(0),
+            )?;
+            return Ok(count as usize);
+        }
+        self.conn
+            .execute(&format!("DELETE FROM change_log WHERE {}", SUPERSEDED), [up_to])
+    }
+
+    /// The sequence number of the latest change, or 0 if nothing changed yet.
+    pub fn last_seq(&self) -> Result<i64> {
+        self.conn
+            .query_row("SELECT COALESCE(MAX(seq), 0) FROM change_log", [], |row| {
+                row.get(0)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::chunk_repo::ChunkRepository;
+    use crate::db::embedding_repo::EmbeddingRepository;
+    use crate::db::file_repo::FileRepository;
+    use crate::db::migrate;
+    use crate::file::FileSource;
+
+    fn setup() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        migrate(&mut conn).unwrap();
+        conn
+    }
+
+    fn ops(conn: &Connection) -> Vec<(String, ChangeOp)> {
+        ChangeLogRepository::new(conn)
+            .since(0, 100)
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.table_name, change.op))
+            .collect()
+    }
+
+    #[test]
+    fn logs_file_inserts_and_updates_once_each() {
+        let conn = setup();
+        let files = FileRepository::new(&conn);
+        files
+            .upsert_file("/tmp/a.txt", "text/plain", "h1", 1, FileSource::Watched, None)
+            .unwrap();
+        files
+            .upsert_file("/tmp/a.txt", "text/plain", "h2", 2, FileSource::Watched, None)
+            .unwrap();
+
+        assert_eq!(
+            ops(&conn),
+            vec![
+                ("files".to_string(), ChangeOp::Insert),
+                ("files".to_string(), ChangeOp::Update),
+            ]
+        );
+    }
+
+    #[test]
+    fn logs_embedding_deletes_cascaded_from_file_delete() {
+        let conn = setup();
+        let file = FileRepository::new(&conn)
+            .upsert_file("/tmp/a.txt", "text/plain", "h", 1, FileSource::Watched, None)
+            .unwrap();
+        let chunk_ids = ChunkRepository::new(&conn)
+            .replace_chunks(&file.id, &[("hello".to_string(), 1)])
+            .unwrap();
+        EmbeddingRepository::new(&conn)
+            .insert_embedding(&file.id, &chunk_ids[0], "m", 2, "[0.0,1.0]")
+            .unwrap();
+        FileRepository::new(&conn).delete_file("/tmp/a.txt").unwrap();
+
+        let changes = ChangeLogRepository::new(&conn).since(0, 100).unwrap();
+        let deletes: Vec<_> = changes
+            .iter()
+            .filter(|change| change.op == ChangeOp::Delete)
+            .map(|change| (change.table_name.as_str(), change.file_id.as_str()))
+            .collect();
+        assert!(deletes.contains(&("embeddings", file.id.as_str())));
+        assert!(deletes.contains(&("files", file.id.as_str())));
+    }
+
+    #[test]
+    fn sequence_numbers_increase_and_resume_after_a_cursor() {
+        let conn = setup();
+        let files = FileRepository::new(&conn);
+        for path in ["/tmp/a.txt", "/tmp/b.txt", "/tmp/c.txt"] {
+            files
+                .upsert_file(path, "text/plain", "h", 1, FileSource::Watched, None)
+                .unwrap();
+        }
+        let log = ChangeLogRepository::new(&conn);
+        let first = log.since(0, 2).unwrap();
+        assert_eq!(first.len(), 2);
+        assert!(first[0].seq < first[1].seq);
+
+        let rest = log.since(first[1].seq, 100).unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].seq, log.last_seq().unwrap());
+    }
+
+    #[test]
+    fn pruning_keeps_each_files_latest_change_up_to_the_cursor() {
+        let conn = setup();
+        let files = FileRepository::new(&conn);
+        for hash in ["h1", "h2", "h3"] {
+            files
+                .upsert_file("/tmp/a.txt", "text/plain", hash, 1, FileSource::Watched, None)
+                .unwrap();
+        }
+        files
+            .upsert_file("/tmp/b.txt", "text/plain", "h", 1, FileSource::Watched, None)
+            .unwrap();
+        let log = ChangeLogRepository::new(&conn);
+        let seqs: Vec<i64> = log.since(0, 100).unwrap().iter().map(|c| c.seq).collect();
+        let last = log.last_seq().unwrap();
+
+        // Changes after the cursor are kept even when superseded.
+        assert_eq!(log.prune_superseded(seqs[0], true).unwrap(), 1);
+        assert_eq!(log.since(0, 100).unwrap().len(), 4);
+        assert_eq!(log.prune_superseded(last, false).unwrap(), 2);
+
+        let left: Vec<i64> = log.since(0, 100).unwrap().iter().map(|c| c.seq).collect();
+        assert_eq!(left, vec![seqs[2], seqs[3]]);
+        assert_eq!(log.last_seq().unwrap(), last);
+    }
+}
@@ -0,0 +1,62 @@
+use std::ops::Deref;
+
+use rusqlite::{Connection, params, Result, Row};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub id: String,
+    pub file_id: String,
+    pub chunk_index: i64,
+    pub content: String,
+    pub token_count: i64,
+}
+
+fn row_to_chunk(row: &Row) -> Result<Chunk> {
+    Ok(Chunk {
+        id: row.get(0)?,
+        file_id: row.get(1)?,
+        chunk_index: row.get(2)?,
+        content: row.get(3)?,
+        token_count: row.get(4)?,
+    })
+}
+
+pub struct ChunkRepository<C> {
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection>> ChunkRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    /// Replaces all chunks of a file (and, through the cascade, their
+    /// embeddings) with `chunks`, given as `(content, token_count)` pairs.
+    /// Returns the new chunk ids in order.
+    pub fn replace_chunks(&self, file_id: &str, chunks: &[(String, usize)]) -> Result<Vec<String>> {
+        self.conn
+            .execute("DELETE FROM chunks WHERE file_id = ?1", [file_id])?;
+
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO chunks (id, file_id, chunk_index, content, token_count) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut ids = Vec::with_capacity(chunks.len());
+        for (index, (content, token_count)) in chunks.iter().enumerate() {
+            let id = Uuid::new_v4().to_string();
+            stmt.execute(params![&id, file_id, index as i64, content, *token_count as i64])?;
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    pub fn get_chunks(&self, file_id: &str) -> Result<Vec<Chunk>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, file_id, chunk_index, content, token_count FROM chunks WHERE file_id = ?1 ORDER BY chunk_index",
+        )?;
+        let chunks = stmt
+            .query_map([file_id], row_to_chunk)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(chunks)
+    }
+}
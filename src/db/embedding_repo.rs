@@ -1,22 +1,242 @@
-use crate::db::Database;
-use rusqlite::{params, Result};
+use std::ops::Deref;
+
+use rusqlite::{Connection, params, Result, Row};
+use serde::Serialize;
 use uuid::Uuid;
 
-pub struct EmbeddingRepository<'db> {
-    db: &'db Database,
+use crate::db::job_repo::JobStatus;
+
+/// A stored vector with the file and chunk it was computed from. Vectors
+/// written before chunking was introduced have no chunk.
+#[derive(Debug, Clone)]
+pub struct StoredEmbedding {
+    /// The node the file was replicated from; `None` for local files.
+    pub node_id: Option<String>,
+    pub file_id: String,
+    pub path: String,
+    pub chunk_index: Option<i64>,
+    pub content: Option<String>,
+    /// The vector as a JSON array.
+    pub embedding: String,
+    /// The file's metadata as a JSON object, if it has any.
+    pub metadata: Option<String>,
+}
+
+fn row_to_stored_embedding(row: &Row) -> Result<StoredEmbedding> {
+    Ok(StoredEmbedding {
+        node_id: row.get(0)?,
+        file_id: row.get(1)?,
+        path: row.get(2)?,
+        chunk_index: row.get(3)?,
+        content: row.get(4)?,
+        embedding: row.get(5)?,
+        metadata: row.get(6)?,
+    })
+}
+
+/// One of a file's vectors, identified by its chunk rather than row ids.
+#[derive(Debug, Clone)]
+pub struct FileEmbedding {
+    pub chunk_index: Option<i64>,
+    pub model: String,
+    pub dimensions: usize,
+    /// The vector as a JSON array.
+    pub embedding: String,
+}
+
+/// Files whose vectors don't reflect them, not counting files waiting in
+/// the queue.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Coverage {
+    /// Files with vectors only from another model, or from before the file
+    /// last changed (its last job failed or was skipped).
+    pub stale: usize,
+    /// Files with no vectors at all that weren't deliberately skipped.
+    pub missing: usize,
 }
 
-impl<'db> EmbeddingRepository<'db> {
-    pub fn new(db: &'db Database) -> Self {
-        Self { db }
+pub struct EmbeddingRepository<C> {
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection>> EmbeddingRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
     }
 
-    pub fn insert_embedding(&self, file_id: &str, embedding: &str) -> Result<()> {
+    pub fn insert_embedding(
+        &self,
+        file_id: &str,
+        chunk_id: &str,
+        model: &str,
+        dimensions: usize,
+        embedding: &str,
+    ) -> Result<()> {
         let id = Uuid::new_v4().to_string();
-        self.db.conn.execute(
-            "INSERT INTO embeddings (id, file_id, embedding) VALUES (?1, ?2, ?3)",
-            params![&id, file_id, embedding],
+        self.conn.execute(
+            "INSERT INTO embeddings (id, file_id, chunk_id, model, dimensions, embedding) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![&id, file_id, chunk_id, model, dimensions as i64, embedding],
         )?;
         Ok(())
     }
+
+    /// Removes a file's embeddings from one model, so they can be rewritten
+    /// without touching vectors from other models.
+    pub fn delete_for_model(&self, file_id: &str, model: &str, dimensions: usize) -> Result<usize> {
+        self.conn.execute(
+            "DELETE FROM embeddings WHERE file_id = ?1 AND model = ?2 AND dimensions = ?3",
+            params![file_id, model, dimensions as i64],
+        )
+    }
+
+    /// Removes all of a file's embeddings, from every model.
+    pub fn delete_for_file(&self, file_id: &str) -> Result<usize> {
+        self.conn
+            .execute("DELETE FROM embeddings WHERE file_id = ?1", [file_id])
+    }
+
+    /// How many files' vectors from `model` with `dimensions` are stale or
+    /// missing.
+    pub fn coverage(&self, model: &str, dimensions: usize) -> Result<Coverage> {
+        self.conn.query_row(
+            r#"
+            WITH state AS (
+                SELECT
+                    f.updated_at,
+                    (SELECT j.status FROM jobs j WHERE j.file_id = f.id
+                     ORDER BY j.created_at DESC, j.rowid DESC LIMIT 1) AS last_job,
+                    EXISTS (SELECT 1 FROM embeddings e WHERE e.file_id = f.id) AS has_vectors,
+                    (SELECT MAX(e.created_at) FROM embeddings e
+                     WHERE e.file_id = f.id AND e.model = ?1 AND e.dimensions = ?2) AS embedded_at
+                FROM files f
+            )
+            SELECT
+                COALESCE(SUM(has_vectors AND (embedded_at IS NULL OR embedded_at < updated_at)), 0),
+                COALESCE(SUM(NOT has_vectors AND last_job IS NOT ?3), 0)
+            FROM state
+            WHERE last_job IS NULL OR last_job NOT IN (?4, ?5)
+            "#,
+            params![
+                model,
+                dimensions as i64,
+                JobStatus::Skipped,
+                JobStatus::Pending,
+                JobStatus::Running
+            ],
+            |row| {
+                Ok(Coverage {
+                    stale: row.get::<_, i64>(0)? as usize,
+                    missing: row.get::<_, i64>(1)? as usize,
+                })
+            },
+        )
+    }
+
+    /// Removes every embedding not produced by `model` with `dimensions`.
+    pub fn delete_other_models(&self, model: &str, dimensions: usize) -> Result<usize> {
+        self.conn.execute(
+            "DELETE FROM embeddings WHERE model != ?1 OR dimensions != ?2",
+            params![model, dimensions as i64],
+        )
+    }
+
+    /// A file's vectors from every model, in chunk order.
+    pub fn get_for_file(&self, file_id: &str) -> Result<Vec<FileEmbedding>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT c.chunk_index, e.model, e.dimensions, e.embedding
+            FROM embeddings e
+            LEFT JOIN chunks c ON c.id = e.chunk_id
+            WHERE e.file_id = ?1
+            ORDER BY e.model, c.chunk_index
+            "#,
+        )?;
+        let embeddings = stmt
+            .query_map([file_id], |row| {
+                Ok(FileEmbedding {
+                    chunk_index: row.get(0)?,
+                    model: row.get(1)?,
+                    dimensions: row.get::<_, i64>(2)? as usize,
+                    embedding: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(embeddings)
+    }
+
+    /// Every vector produced by `model` with `dimensions`, local and
+    /// replicated from other nodes.
+    pub fn get_for_model(&self, model: &str, dimensions: usize) -> Result<Vec<StoredEmbedding>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT NULL, e.file_id, f.path, c.chunk_index, c.content, e.embedding, f.metadata
+            FROM embeddings e
+            JOIN files f ON f.id = e.file_id
+            LEFT JOIN chunks c ON c.id = e.chunk_id
+            WHERE e.model = ?1 AND e.dimensions = ?2
+            UNION ALL
+            SELECT ne.node_id, ne.file_id, nf.path, ne.chunk_index, nc.content, ne.embedding, nf.metadata
+            FROM node_embeddings ne
+            JOIN node_files nf ON nf.node_id = ne.node_id AND nf.file_id = ne.file_id
+            LEFT JOIN node_chunks nc
+                ON nc.node_id = ne.node_id AND nc.file_id = ne.file_id AND nc.chunk_index = ne.chunk_index
+            WHERE ne.model = ?1 AND ne.dimensions = ?2
+            "#,
+        )?;
+        let embeddings = stmt
+            .query_map(params![model, dimensions as i64], row_to_stored_embedding)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(embeddings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::chunk_repo::ChunkRepository;
+    use crate::db::file_repo::FileRepository;
+    use crate::db::job_repo::{JobRepository, JobStatus};
+    use crate::db::migrate;
+    use crate::file::FileSource;
+
+    #[test]
+    fn coverage_counts_files_not_waiting_in_the_queue() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        migrate(&mut conn).unwrap();
+        let files = FileRepository::new(&conn);
+        let jobs = JobRepository::new(&conn);
+        let embeddings = EmbeddingRepository::new(&conn);
+        let embed = |path: &str, model: &str, status: JobStatus| {
+            let file = files
+                .upsert_file(path, "text/plain", "h", 1, FileSource::Watched, None)
+                .unwrap();
+            let job = jobs.insert_job(&file.id).unwrap();
+            jobs.update_job_batch(&[job], status, None).unwrap();
+            if !model.is_empty() {
+                let chunk = ChunkRepository::new(&conn)
+                    .replace_chunks(&file.id, &[("a".to_string(), 1)])
+                    .unwrap();
+                embeddings
+                    .insert_embedding(&file.id, &chunk[0], model, 4, "[0,0,0,0]")
+                    .unwrap();
+            }
+            file
+        };
+
+        embed("/a", "m", JobStatus::Completed);
+        embed("/old-model", "old", JobStatus::Completed);
+        embed("/failed", "", JobStatus::Failed);
+        embed("/skipped", "", JobStatus::Skipped);
+        embed("/queued", "", JobStatus::Pending);
+        let changed = embed("/changed", "m", JobStatus::Failed);
+        conn.execute(
+            "UPDATE embeddings SET created_at = datetime('now', '-1 hour') WHERE file_id = ?1",
+            [&changed.id],
+        )
+        .unwrap();
+
+        let coverage = embeddings.coverage("m", 4).unwrap();
+        assert_eq!((coverage.stale, coverage.missing), (2, 1));
+    }
 }
@@ -1,9 +1,19 @@
-use crate::db::Database;
-use crate::file::File;
-use rusqlite::{params, Result, Row};
+use std::ops::Deref;
+
+use crate::file::{File, FileSource};
+use rusqlite::types::Type;
+use rusqlite::{Connection, params, OptionalExtension, Result, Row};
+use serde::Serialize;
 use uuid::Uuid;
 
+const FILE_COLUMNS: &str = "id, path, file_type, hash, size, created_at, updated_at, source, metadata";
+
 fn row_to_file(row: &Row) -> Result<File> {
+    let metadata = row
+        .get::<_, Option<String>>(8)?
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(8, Type::Text, Box::new(e)))?;
     Ok(File {
         id: row.get(0)?,
         path: row.get(1)?,
@@ -12,16 +22,28 @@ fn row_to_file(row: &Row) -> Result<File> {
         size: row.get(4)?,
         created_at: row.get(5)?,
         updated_at: row.get(6)?,
+        source: row.get(7)?,
+        metadata,
     })
 }
 
-pub struct FileRepository<'db> {
-    db: &'db Database,
+/// How many files of one MIME type are tracked under a watched root.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileTypeCount {
+    /// `None` for files outside the watched directory, e.g. indexed by path
+    /// or as text.
+    pub root: Option<String>,
+    pub file_type: String,
+    pub files: usize,
+}
+
+pub struct FileRepository<C> {
+    conn: C,
 }
 
-impl<'db> FileRepository<'db> {
-    pub fn new(db: &'db Database) -> Self {
-        Self { db }
+impl<C: Deref<Target = Connection>> FileRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
     }
 
     pub fn upsert_file(
@@ -30,38 +52,126 @@ impl<'db> FileRepository<'db> {
         file_type: &str,
         hash: &str,
         size: i64,
+        source: FileSource,
+        metadata: Option<&serde_json::Value>,
     ) -> Result<File> {
         let id = Uuid::new_v4().to_string();
-        let file = self.db.conn.query_row(
-            r#"
-            INSERT INTO files (id, path, file_type, hash, size) 
-            VALUES (?1, ?2, ?3, ?4, ?5)
-            ON CONFLICT(path) DO UPDATE SET
-                file_type = excluded.file_type,
-                hash = excluded.hash,
-                size = excluded.size,
-                updated_at = CURRENT_TIMESTAMP
-            RETURNING id, path, file_type, hash, size, created_at, updated_at
-            "#,
-            params![&id, path, file_type, hash, size],
+        let metadata = metadata.map(|m| m.to_string());
+        let file = self.conn.query_row(
+            &format!(
+                r#"
+                INSERT INTO files (id, path, file_type, hash, size, source, metadata)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT(path) DO UPDATE SET
+                    file_type = excluded.file_type,
+                    hash = excluded.hash,
+                    size = excluded.size,
+                    source = excluded.source,
+                    metadata = excluded.metadata,
+                    updated_at = CURRENT_TIMESTAMP
+                RETURNING {FILE_COLUMNS}
+                "#
+            ),
+            params![&id, path, file_type, hash, size, source, metadata],
             row_to_file,
         )?;
         Ok(file)
     }
 
     pub fn get_file(&self, id: &str) -> Result<File> {
-        let file =
-            self.db.conn
-                .query_row("SELECT * FROM files WHERE id = ($1)", [id], row_to_file)?;
+        let file = self.conn.query_row(
+            &format!("SELECT {FILE_COLUMNS} FROM files WHERE id = ?1"),
+            [id],
+            row_to_file,
+        )?;
         Ok(file)
     }
 
+    /// Like [`FileRepository::get_file`], but `None` when the file is gone.
+    pub fn find_file(&self, id: &str) -> Result<Option<File>> {
+        self.get_file(id).optional()
+    }
+
+    pub fn find_by_path(&self, path: &str) -> Result<Option<File>> {
+        self.conn
+            .query_row(
+                &format!("SELECT {FILE_COLUMNS} FROM files WHERE path = ?1"),
+                [path],
+                row_to_file,
+            )
+            .optional()
+    }
+
+    /// Every indexed file, ordered by path.
+    pub fn list_files(&self) -> Result<Vec<File>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {FILE_COLUMNS} FROM files ORDER BY path"))?;
+        let files = stmt
+            .query_map([], row_to_file)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(files)
+    }
+
+    /// Files per MIME type, split by whether they are under `root`.
+    pub fn count_by_type(&self, root: &str) -> Result<Vec<FileTypeCount>> {
+        let prefix = root.trim_end_matches('/');
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT substr(path, 1, length(?1) + 1) = ?1 || '/' AS under_root, file_type, COUNT(*)
+            FROM files
+            GROUP BY under_root, file_type
+            ORDER BY under_root DESC, COUNT(*) DESC, file_type
+            "#,
+        )?;
+        let counts = stmt
+            .query_map([prefix], |row| {
+                Ok(FileTypeCount {
+                    root: row.get::<_, bool>(0)?.then(|| root.to_string()),
+                    file_type: row.get(1)?,
+                    files: row.get::<_, i64>(2)? as usize,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(counts)
+    }
+
     pub fn delete_file(&self, path: &str) -> Result<File> {
-        let file = self.db.conn.query_row(
-            "DELETE FROM files WHERE path = ?1 RETURNING id, path, file_type, hash, size, created_at, updated_at",
+        let file = self.conn.query_row(
+            &format!("DELETE FROM files WHERE path = ?1 RETURNING {FILE_COLUMNS}"),
             [path],
             row_to_file,
         )?;
         Ok(file)
     }
+
+    /// Stores the text extracted from a file, replacing any earlier version.
+    pub fn upsert_content(&self, file_id: &str, content: &str) -> Result<()> {
+        self.conn.execute(
+            r#"
+            INSERT INTO file_contents (file_id, content) VALUES (?1, ?2)
+            ON CONFLICT(file_id) DO UPDATE SET
+                content = excluded.content,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+            params![file_id, content],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_content(&self, file_id: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT content FROM file_contents WHERE file_id = ?1",
+                [file_id],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub fn delete_content(&self, file_id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM file_contents WHERE file_id = ?1", [file_id])?;
+        Ok(())
+    }
 }
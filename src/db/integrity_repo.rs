@@ -0,0 +1,136 @@
+use std::ops::Deref;
+
+use rusqlite::{Connection, Result};
+
+/// Rows of `table` whose parent in `parent` is gone, selected by `condition`.
+struct OrphanCheck {
+    table: &'static str,
+    parent: &'static str,
+    condition: &'static str,
+}
+
+/// Deletes cascade through foreign keys, but a database written with them
+/// off (by an old bako or another tool) can still hold rows like these.
+const ORPHAN_CHECKS: &[OrphanCheck] = &[
+    OrphanCheck {
+        table: "jobs",
+        parent: "files",
+        condition: "file_id NOT IN (SELECT id FROM files)",
+    },
+    OrphanCheck {
+        table: "chunks",
+        parent: "files",
+        condition: "file_id NOT IN (SELECT id FROM files)",
+    },
+    OrphanCheck {
+        table: "embeddings",
+        parent: "files",
+        condition: "file_id NOT IN (SELECT id FROM files)",
+    },
+    OrphanCheck {
+        table: "embeddings",
+        parent: "chunks",
+        condition: "chunk_id IS NOT NULL AND chunk_id NOT IN (SELECT id FROM chunks)",
+    },
+    OrphanCheck {
+        table: "file_contents",
+        parent: "files",
+        condition: "file_id NOT IN (SELECT id FROM files)",
+    },
+];
+
+/// Rows found without their parent row.
+#[derive(Debug, Clone)]
+pub struct Orphans {
+    pub table: &'static str,
+    pub parent: &'static str,
+    pub count: usize,
+}
+
+pub struct IntegrityRepository<C> {
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection>> IntegrityRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    /// The problems `PRAGMA integrity_check` reports; empty when the file is
+    /// sound.
+    pub fn integrity_check(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("PRAGMA integrity_check")?;
+        let messages = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(messages.into_iter().filter(|m| m != "ok").collect())
+    }
+
+    /// Orphaned rows per check, leaving out checks that found none.
+    pub fn orphans(&self) -> Result<Vec<Orphans>> {
+        let mut found = Vec::new();
+        for check in ORPHAN_CHECKS {
+            let count: i64 = self.conn.query_row(
+                &format!("SELECT COUNT(*) FROM {} WHERE {}", check.table, check.condition),
+                [],
+                |row| row.get(0),
+            )?;
+            if count > 0 {
+                found.push(Orphans {
+                    table: check.table,
+                    parent: check.parent,
+                    count: count as usize,
+                });
+            }
+        }
+        Ok(found)
+    }
+
+    /// Deletes every orphaned row and returns how many there were.
+    pub fn delete_orphans(&self) -> Result<usize> {
+        let mut deleted = 0;
+        for check in ORPHAN_CHECKS {
+            deleted += self.conn.execute(
+                &format!("DELETE FROM {} WHERE {}", check.table, check.condition),
+                [],
+            )?;
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::file_repo::FileRepository;
+    use crate::db::job_repo::JobRepository;
+    use crate::db::migrate;
+    use crate::file::FileSource;
+
+    #[test]
+    fn finds_and_deletes_rows_left_behind_with_foreign_keys_off() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        migrate(&mut conn).unwrap();
+        let file = FileRepository::new(&conn)
+            .upsert_file("/tmp/a.txt", "text/plain", "h", 1, FileSource::Watched, None)
+            .unwrap();
+        JobRepository::new(&conn).insert_job(&file.id).unwrap();
+        FileRepository::new(&conn).upsert_content(&file.id, "a").unwrap();
+
+        conn.pragma_update(None, "foreign_keys", "OFF").unwrap();
+        conn.execute("DELETE FROM files", []).unwrap();
+
+        let integrity = IntegrityRepository::new(&conn);
+        assert!(integrity.integrity_check().unwrap().is_empty());
+        let orphans: Vec<_> = integrity
+            .orphans()
+            .unwrap()
+            .into_iter()
+            .map(|o| (o.table, o.count))
+            .collect();
+        assert_eq!(orphans, vec![("jobs", 1), ("file_contents", 1)]);
+        assert_eq!(integrity.delete_orphans().unwrap(), 2);
+        assert!(integrity.orphans().unwrap().is_empty());
+    }
+}
@@ -1,14 +1,176 @@
-use crate::db::Database;
-use rusqlite::{params, Result, Row};
+use std::ops::Deref;
+use std::time::Duration;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{Connection, Result, Row, params};
+use serde::Serialize;
 use uuid::Uuid;
 
+/// Where a job is in its lifecycle; stored as its lowercase name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    /// Deliberately not embedded (oversize, sensitive content, path rules).
+    Skipped,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+            JobStatus::Skipped => "skipped",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Option<JobStatus> {
+        match s {
+            "pending" => Some(JobStatus::Pending),
+            "running" => Some(JobStatus::Running),
+            "completed" => Some(JobStatus::Completed),
+            "failed" => Some(JobStatus::Failed),
+            "skipped" => Some(JobStatus::Skipped),
+            _ => None,
+        }
+    }
+
+    /// Whether the job is done with, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed | JobStatus::Failed | JobStatus::Skipped
+        )
+    }
+}
+
+impl std::fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl ToSql for JobStatus {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for JobStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        JobStatus::from_string(s)
+            .ok_or_else(|| FromSqlError::Other(format!("Unknown job status: {}", s).into()))
+    }
+}
+
+/// Why a job was queued; stored as its lowercase name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    /// The file changed.
+    Index,
+    /// The file needs vectors from a new embedding model.
+    Reembed,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Index => "index",
+            JobKind::Reembed => "reembed",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Option<JobKind> {
+        match s {
+            "index" => Some(JobKind::Index),
+            "reembed" => Some(JobKind::Reembed),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for JobKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl ToSql for JobKind {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for JobKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        JobKind::from_string(s)
+            .ok_or_else(|| FromSqlError::Other(format!("Unknown job kind: {}", s).into()))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Job {
     pub id: String,
     pub file_id: String,
-    pub status: String,
+    pub status: JobStatus,
     pub error_message: Option<String>,
     pub created_at: String,
+    pub kind: JobKind,
+}
+
+/// Number of jobs in each status.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct JobCounts {
+    pub pending: usize,
+    pub running: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+/// What became of the files in one indexing run: those with jobs still open,
+/// or created or finished since the run began. Each file counts once, by its
+/// latest job.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RunCounts {
+    /// Files seen changed or queued for re-embedding.
+    pub discovered: usize,
+    /// Of those, files read and hashed in this run; `reembed` jobs reuse the
+    /// stored text instead.
+    pub hashed: usize,
+    /// Of the hashed files, those whose text was extracted and stored.
+    pub extracted: usize,
+    pub embedded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Files still waiting to be embedded.
+    pub pending: usize,
+}
+
+/// Jobs finished over a recent stretch of time, for estimating how long the
+/// queue will take.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Throughput {
+    pub jobs: usize,
+    /// From the first of those jobs finishing until now.
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// How long `remaining` jobs should take at this rate; `None` when too
+    /// little has finished recently to tell.
+    pub fn eta(&self, remaining: usize) -> Option<Duration> {
+        if self.jobs < 2 || self.elapsed.is_zero() {
+            return None;
+        }
+        Some(self.elapsed.mul_f64(remaining as f64 / self.jobs as f64))
+    }
 }
 
 fn row_to_job(row: &Row) -> Result<Job> {
@@ -18,76 +180,611 @@ fn row_to_job(row: &Row) -> Result<Job> {
         status: row.get(2)?,
         error_message: row.get(3)?,
         created_at: row.get(4)?,
+        kind: row.get(5)?,
     })
 }
 
-pub struct JobRepository<'db> {
-    db: &'db Database,
+pub struct JobRepository<C> {
+    conn: C,
 }
 
-impl<'db> JobRepository<'db> {
-    pub fn new(db: &'db Database) -> Self {
-        Self { db }
+impl<C: Deref<Target = Connection>> JobRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
     }
 
     pub fn insert_job(&self, file_id: &str) -> Result<String> {
         let id = Uuid::new_v4().to_string();
-        self.db.conn.execute(
-            "INSERT INTO jobs (id, file_id, status, error_message) VALUES (?1, ?2, ?3, ?4)",
-            params![&id, file_id, "pending", None::<String>],
+        self.conn.execute(
+            "INSERT INTO jobs (id, file_id, status, error_message, kind) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![&id, file_id, JobStatus::Pending, None::<String>, JobKind::Index],
         )?;
         Ok(id)
     }
 
-    pub fn get_jobs_by_file_id(&self, file_id: &str, status: &str) -> Result<Vec<Job>> {
-        let base_query = "SELECT id, file_id, status, error_message, created_at FROM jobs WHERE file_id = ?1";
-        let jobs = if status.eq_ignore_ascii_case("any") {
-            let mut stmt = self.db.conn.prepare(base_query)?;
-            stmt.query_map(params![file_id], row_to_job)?
-                .collect::<Result<Vec<_>, _>>()?
-        } else {
-            let full_query = format!("{} AND status = ?2", base_query);
-            let mut stmt = self.db.conn.prepare(&full_query)?;
-            stmt.query_map(params![file_id, status], row_to_job)?
-                .collect::<Result<Vec<_>, _>>()?
-        };
+    /// Queues a `reembed` job for every file that has no pending `reembed` job
+    /// and no embeddings from `model` with `dimensions`. Returns how many were
+    /// queued.
+    pub fn insert_reembed_jobs(&self, model: &str, dimensions: usize) -> Result<usize> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT f.id FROM files f
+            WHERE NOT EXISTS (
+                SELECT 1 FROM embeddings e
+                WHERE e.file_id = f.id AND e.model = ?1 AND e.dimensions = ?2
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM jobs j
+                WHERE j.file_id = f.id AND j.kind = ?3 AND j.status IN (?4, ?5)
+            )
+            "#,
+        )?;
+        let file_ids = stmt
+            .query_map(
+                params![
+                    model,
+                    dimensions as i64,
+                    JobKind::Reembed,
+                    JobStatus::Pending,
+                    JobStatus::Running
+                ],
+                |row| {
+                row.get::<_, String>(0)
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for file_id in &file_ids {
+            let id = Uuid::new_v4().to_string();
+            self.conn.execute(
+                "INSERT INTO jobs (id, file_id, status, kind) VALUES (?1, ?2, ?3, ?4)",
+                params![&id, file_id, JobStatus::Pending, JobKind::Reembed],
+            )?;
+        }
+        Ok(file_ids.len())
+    }
+
+    /// Number of `reembed` jobs that are still waiting or in progress.
+    pub fn count_open_reembed_jobs(&self) -> Result<usize> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM jobs WHERE kind = ?1 AND status IN (?2, ?3)",
+            params![JobKind::Reembed, JobStatus::Pending, JobStatus::Running],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Jobs of a file, optionally only those with `status`.
+    pub fn get_jobs_by_file_id(
+        &self,
+        file_id: &str,
+        status: Option<JobStatus>,
+    ) -> Result<Vec<Job>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, file_id, status, error_message, created_at, kind FROM jobs WHERE file_id = ?1 AND (?2 IS NULL OR status = ?2)",
+        )?;
+        let jobs = stmt
+            .query_map(params![file_id, status], row_to_job)?
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(jobs)
     }
 
-    pub fn get_jobs(&self, status: &str) -> Result<Vec<Job>> {
-        let mut stmt = self.db.conn.prepare(
-            "SELECT id, file_id, status, error_message, created_at FROM jobs WHERE status = ?1",
+    /// Jobs with `status`, oldest first, at most `limit` of them if given.
+    pub fn get_jobs(&self, status: JobStatus, limit: Option<usize>) -> Result<Vec<Job>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, file_id, status, error_message, created_at, kind FROM jobs WHERE status = ?1 ORDER BY created_at, rowid LIMIT ?2",
         )?;
+        let limit = limit.map_or(-1, |limit| limit as i64);
         let jobs = stmt
-            .query_map([status], row_to_job)?
+            .query_map(params![status, limit], row_to_job)?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(jobs)
     }
 
-    pub fn update_job_batch(&self, job_ids: Vec<String>, status: &str, error_message: Option<&str>) -> Result<()> {
-        let mut sql = String::new();
-        for job_id in &job_ids {
-            let err_val = match error_message {
-                Some(msg) => format!("'{}'", msg.replace('\'', "''")),
-                None => "NULL".to_string(),
-            };
-            sql.push_str(&format!(
-                "UPDATE jobs SET status = '{}', error_message = {} WHERE id = '{}';",
-                status.replace('\'', "''"),
-                err_val,
-                job_id.replace('\'', "''"),
-            ));
+    /// Marks up to `limit` of the oldest pending jobs as running and returns
+    /// them, oldest first. The jobs are taken in a single statement, so two
+    /// queue runs never get the same job.
+    pub fn claim_jobs(&self, limit: usize) -> Result<Vec<Job>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            UPDATE jobs SET status = ?1, updated_at = CURRENT_TIMESTAMP
+            WHERE id IN (SELECT id FROM jobs WHERE status = ?2 ORDER BY created_at, rowid LIMIT ?3)
+            RETURNING id, file_id, status, error_message, created_at, kind
+            "#,
+        )?;
+        let mut jobs = stmt
+            .query_map(
+                params![JobStatus::Running, JobStatus::Pending, limit as i64],
+                row_to_job,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        jobs.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(jobs)
+    }
+
+    /// Puts jobs that have been running for longer than `stale_after` back in
+    /// the queue, for jobs left behind by a bako that stopped mid-run. Jobs a
+    /// live run claimed more recently are left to it. Returns how many were
+    /// requeued.
+    pub fn requeue_stale(&self, stale_after: Duration) -> Result<usize> {
+        self.conn.execute(
+            r#"
+            UPDATE jobs SET status = ?1, updated_at = CURRENT_TIMESTAMP
+            WHERE status = ?2 AND COALESCE(updated_at, created_at) < datetime('now', ?3)
+            "#,
+            params![
+                JobStatus::Pending,
+                JobStatus::Running,
+                format!("-{} seconds", stale_after.as_secs())
+            ],
+        )
+    }
+
+    /// Puts the jobs in `job_ids` that are still running back in the queue,
+    /// for a run that failed before dealing with all it claimed. Returns how
+    /// many were requeued.
+    pub fn release_jobs(&self, job_ids: &[String]) -> Result<usize> {
+        let ids = serde_json::to_string(job_ids)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            r#"
+            UPDATE jobs SET status = ?1, updated_at = CURRENT_TIMESTAMP
+            WHERE status = ?2 AND id IN (SELECT value FROM json_each(?3))
+            "#,
+            params![JobStatus::Pending, JobStatus::Running, ids],
+        )
+    }
+
+    /// Sets the status and error message of every job in `job_ids` in one
+    /// statement, stamping `finished_at` for finished statuses. The ids are
+    /// bound as a single JSON array, so any number of them fits without
+    /// hitting SQLite's limit on bound parameters.
+    pub fn update_job_batch(
+        &self,
+        job_ids: &[String],
+        status: JobStatus,
+        error_message: Option<&str>,
+    ) -> Result<usize> {
+        let ids = serde_json::to_string(job_ids)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            "UPDATE jobs SET status = ?1, error_message = ?2, updated_at = CURRENT_TIMESTAMP, finished_at = CASE WHEN ?4 THEN CURRENT_TIMESTAMP END WHERE id IN (SELECT value FROM json_each(?3))",
+            params![status, error_message, ids, status.is_finished()],
+        )
+    }
+
+    /// Deletes jobs that completed more than `days` days ago, or counts them
+    /// with `dry_run`. Jobs finished before `finished_at` was recorded are
+    /// aged by when they were created.
+    pub fn delete_completed_older_than(&self, days: u32, dry_run: bool) -> Result<usize> {
+        let cutoff = format!("-{} days", days);
+        if dry_run {
+            let count: i64 = self.conn.query_row(
+                "SELECT COUNT(*) FROM jobs WHERE status = ?1 AND COALESCE(finished_at, created_at) < datetime('now', ?2)",
+                params![JobStatus::Completed, cutoff],
+                |row| row.get(0),
+            )?;
+            return Ok(count as usize);
         }
-        self.db.conn.execute_batch(&sql)
+        self.conn.execute(
+            "DELETE FROM jobs WHERE status = ?1 AND COALESCE(finished_at, created_at) < datetime('now', ?2)",
+            params![JobStatus::Completed, cutoff],
+        )
     }
 
     pub fn get_queue_size(&self) -> Result<usize> {
-        let count: i64 =
-            self.db.conn
-                .query_row("SELECT COUNT(*) FROM jobs WHERE status = 'pending'", [], |row| {
-                    row.get(0)
-                })?;
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM jobs WHERE status = ?1",
+            [JobStatus::Pending],
+            |row| row.get(0),
+        )?;
 
         Ok(count as usize)
     }
+
+    pub fn count_by_status(&self) -> Result<JobCounts> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT status, COUNT(*) FROM jobs GROUP BY status")?;
+        let mut rows = stmt.query([])?;
+        let mut counts = JobCounts::default();
+        while let Some(row) = rows.next()? {
+            let count = row.get::<_, i64>(1)? as usize;
+            match row.get(0)? {
+                JobStatus::Pending => counts.pending = count,
+                JobStatus::Running => counts.running = count,
+                JobStatus::Completed => counts.completed = count,
+                JobStatus::Failed => counts.failed = count,
+                JobStatus::Skipped => counts.skipped = count,
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Jobs finished within the last `window`.
+    pub fn recent_throughput(&self, window: Duration) -> Result<Throughput> {
+        let (jobs, elapsed_secs): (i64, Option<f64>) = self.conn.query_row(
+            r#"
+            SELECT COUNT(*), (julianday('now') - julianday(MIN(finished_at))) * 86400
+            FROM jobs WHERE finished_at >= datetime('now', ?1)
+            "#,
+            [format!("-{} seconds", window.as_secs())],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(Throughput {
+            jobs: jobs as usize,
+            elapsed: Duration::from_secs_f64(elapsed_secs.unwrap_or(0.0).max(0.0)),
+        })
+    }
+
+    /// Counts for the run that began at `since` (UTC, as SQLite formats
+    /// timestamps).
+    pub fn run_counts(&self, since: &str) -> Result<RunCounts> {
+        self.conn.query_row(
+            r#"
+            WITH run AS (
+                SELECT
+                    file_id,
+                    status,
+                    MAX(kind = ?7) OVER (PARTITION BY file_id) AS hashed,
+                    ROW_NUMBER() OVER (PARTITION BY file_id ORDER BY created_at DESC, rowid DESC) AS n
+                FROM jobs
+                WHERE status IN (?5, ?6) OR created_at > ?1 OR finished_at > ?1
+            )
+            SELECT
+                COUNT(*),
+                COALESCE(SUM(hashed), 0),
+                COALESCE(SUM(hashed AND EXISTS (SELECT 1 FROM file_contents c WHERE c.file_id = run.file_id)), 0),
+                COALESCE(SUM(status = ?2), 0),
+                COALESCE(SUM(status = ?3), 0),
+                COALESCE(SUM(status = ?4), 0),
+                COALESCE(SUM(status IN (?5, ?6)), 0)
+            FROM run
+            WHERE n = 1
+            "#,
+            params![
+                since,
+                JobStatus::Completed,
+                JobStatus::Skipped,
+                JobStatus::Failed,
+                JobStatus::Pending,
+                JobStatus::Running,
+                JobKind::Index
+            ],
+            |row| {
+                let count = |i| row.get::<_, i64>(i).map(|n| n as usize);
+                Ok(RunCounts {
+                    discovered: count(0)?,
+                    hashed: count(1)?,
+                    extracted: count(2)?,
+                    embedded: count(3)?,
+                    skipped: count(4)?,
+                    failed: count(5)?,
+                    pending: count(6)?,
+                })
+            },
+        )
+    }
+
+    /// When the most recent job finished (UTC), if any has.
+    pub fn last_finished_at(&self) -> Result<Option<String>> {
+        self.conn
+            .query_row("SELECT MAX(finished_at) FROM jobs", [], |row| row.get(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::file_repo::FileRepository;
+    use crate::db::migrate;
+    use crate::file::FileSource;
+
+    fn setup() -> (Connection, String) {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        migrate(&mut conn).unwrap();
+        let file = FileRepository::new(&conn)
+            .upsert_file("/tmp/notes.txt", "text/plain", "hash", 5, FileSource::Watched, None)
+            .unwrap();
+        (conn, file.id)
+    }
+
+    fn job(conn: &Connection, id: &str) -> Job {
+        conn.query_row(
+            "SELECT id, file_id, status, error_message, created_at, kind FROM jobs WHERE id = ?1",
+            [id],
+            row_to_job,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn update_job_batch_stores_messages_verbatim() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let id = jobs.insert_job(&file_id).unwrap();
+        let message = "can't parse \"config\"\nline 2: it''s broken; DROP TABLE jobs; --";
+
+        let updated = jobs
+            .update_job_batch(std::slice::from_ref(&id), JobStatus::Failed, Some(message))
+            .unwrap();
+
+        assert_eq!(updated, 1);
+        let job = job(&conn, &id);
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.error_message.as_deref(), Some(message));
+    }
+
+    #[test]
+    fn update_job_batch_only_touches_listed_jobs() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let first = jobs.insert_job(&file_id).unwrap();
+        let second = jobs.insert_job(&file_id).unwrap();
+        let third = jobs.insert_job(&file_id).unwrap();
+
+        let updated = jobs
+            .update_job_batch(
+                &[first.clone(), third.clone(), "' OR 1=1 --".to_string()],
+                JobStatus::Completed,
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(updated, 2);
+        assert_eq!(job(&conn, &first).status, JobStatus::Completed);
+        assert_eq!(job(&conn, &second).status, JobStatus::Pending);
+        assert_eq!(job(&conn, &third).status, JobStatus::Completed);
+    }
+
+    #[test]
+    fn update_job_batch_clears_the_message() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let id = jobs.insert_job(&file_id).unwrap();
+        jobs.update_job_batch(
+            std::slice::from_ref(&id),
+            JobStatus::Failed,
+            Some("timeout"),
+        )
+        .unwrap();
+
+        jobs.update_job_batch(std::slice::from_ref(&id), JobStatus::Pending, None)
+            .unwrap();
+
+        assert_eq!(job(&conn, &id).error_message, None);
+        assert_eq!(jobs.get_queue_size().unwrap(), 1);
+    }
+
+    #[test]
+    fn update_job_batch_accepts_no_jobs() {
+        let (conn, _) = setup();
+        let updated = JobRepository::new(&conn)
+            .update_job_batch(&[], JobStatus::Completed, None)
+            .unwrap();
+        assert_eq!(updated, 0);
+    }
+
+    #[test]
+    fn get_jobs_filters_by_status() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let done = jobs.insert_job(&file_id).unwrap();
+        let pending = jobs.insert_job(&file_id).unwrap();
+        jobs.update_job_batch(std::slice::from_ref(&done), JobStatus::Completed, None)
+            .unwrap();
+
+        let ids = |jobs: Vec<Job>| jobs.into_iter().map(|job| job.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(jobs.get_jobs(JobStatus::Pending, None).unwrap()),
+            vec![pending.clone()]
+        );
+        assert_eq!(
+            ids(jobs
+                .get_jobs_by_file_id(&file_id, Some(JobStatus::Completed))
+                .unwrap()),
+            vec![done]
+        );
+        assert_eq!(jobs.get_jobs_by_file_id(&file_id, None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn get_jobs_returns_the_oldest_first_up_to_the_limit() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let ids: Vec<String> = (0..3).map(|_| jobs.insert_job(&file_id).unwrap()).collect();
+
+        let first_two: Vec<String> = jobs
+            .get_jobs(JobStatus::Pending, Some(2))
+            .unwrap()
+            .into_iter()
+            .map(|job| job.id)
+            .collect();
+
+        assert_eq!(first_two, ids[..2]);
+        assert_eq!(jobs.get_jobs(JobStatus::Pending, None).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn claimed_jobs_are_running_and_not_claimed_twice() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let ids: Vec<String> = (0..3).map(|_| jobs.insert_job(&file_id).unwrap()).collect();
+        let claim = |limit| -> Vec<String> {
+            jobs.claim_jobs(limit)
+                .unwrap()
+                .into_iter()
+                .map(|job| {
+                    assert_eq!(job.status, JobStatus::Running);
+                    assert_eq!(job.kind, JobKind::Index);
+                    job.id
+                })
+                .collect()
+        };
+
+        assert_eq!(claim(2), ids[..2]);
+        assert_eq!(claim(2), ids[2..]);
+        assert!(claim(2).is_empty());
+        assert_eq!(jobs.get_queue_size().unwrap(), 0);
+
+        // Only jobs claimed long ago are taken to be left behind.
+        conn.execute(
+            "UPDATE jobs SET updated_at = datetime('now', '-2 hours') WHERE id = ?1",
+            [&ids[0]],
+        )
+        .unwrap();
+        assert_eq!(jobs.requeue_stale(Duration::from_secs(3600)).unwrap(), 1);
+        assert_eq!(jobs.get_queue_size().unwrap(), 1);
+
+        // Releasing a batch leaves jobs it already finished alone.
+        jobs.update_job_batch(&ids[1..2], JobStatus::Completed, None)
+            .unwrap();
+        assert_eq!(jobs.release_jobs(&ids).unwrap(), 1);
+        assert_eq!(jobs.get_queue_size().unwrap(), 2);
+    }
+
+    #[test]
+    fn deletes_only_completed_jobs_past_retention() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let old_done = jobs.insert_job(&file_id).unwrap();
+        let old_failed = jobs.insert_job(&file_id).unwrap();
+        let new_done = jobs.insert_job(&file_id).unwrap();
+        jobs.update_job_batch(&[old_done.clone(), new_done.clone()], JobStatus::Completed, None)
+            .unwrap();
+        jobs.update_job_batch(std::slice::from_ref(&old_failed), JobStatus::Failed, Some("x"))
+            .unwrap();
+        // A job queued long ago but completed recently is kept.
+        conn.execute(
+            "UPDATE jobs SET created_at = datetime('now', '-40 days') WHERE id IN (?1, ?2, ?3)",
+            params![old_done, old_failed, new_done],
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE jobs SET finished_at = datetime('now', '-35 days') WHERE id = ?1",
+            [&old_done],
+        )
+        .unwrap();
+
+        assert_eq!(jobs.delete_completed_older_than(30, true).unwrap(), 1);
+        assert_eq!(jobs.get_jobs_by_file_id(&file_id, None).unwrap().len(), 3);
+        assert_eq!(jobs.delete_completed_older_than(30, false).unwrap(), 1);
+        let left: Vec<_> = jobs
+            .get_jobs_by_file_id(&file_id, None)
+            .unwrap()
+            .into_iter()
+            .map(|job| job.id)
+            .collect();
+        assert!(left.contains(&old_failed) && left.contains(&new_done));
+        assert_eq!(left.len(), 2);
+    }
+
+    #[test]
+    fn counts_jobs_and_estimates_from_finished_ones() {
+        let (conn, file_id) = setup();
+        let jobs = JobRepository::new(&conn);
+        let ids: Vec<String> = (0..5).map(|_| jobs.insert_job(&file_id).unwrap()).collect();
+        jobs.update_job_batch(&ids[..2], JobStatus::Completed, None)
+            .unwrap();
+        jobs.update_job_batch(&ids[2..3], JobStatus::Failed, Some("x"))
+            .unwrap();
+        conn.execute(
+            "UPDATE jobs SET finished_at = datetime('now', '-60 seconds') WHERE id = ?1",
+            [&ids[0]],
+        )
+        .unwrap();
+
+        let counts = jobs.count_by_status().unwrap();
+        assert_eq!((counts.pending, counts.completed, counts.failed), (2, 2, 1));
+        assert!(jobs.last_finished_at().unwrap().is_some());
+
+        let throughput = jobs.recent_throughput(Duration::from_secs(3600)).unwrap();
+        assert_eq!(throughput.jobs, 3);
+        // Three jobs in about a minute: two more take about 40 seconds.
+        let eta = throughput.eta(counts.pending).unwrap();
+        assert!(eta > Duration::from_secs(30) && eta < Duration::from_secs(50));
+        assert!(Throughput::default().eta(2).is_none());
+
+        jobs.update_job_batch(&ids[..1], JobStatus::Pending, None)
+            .unwrap();
+        assert_eq!(
+            jobs.recent_throughput(Duration::from_secs(3600)).unwrap().jobs,
+            2
+        );
+    }
+
+    #[test]
+    fn run_counts_cover_open_jobs_and_those_since_the_start() {
+        let (conn, earlier) = setup();
+        let files = FileRepository::new(&conn);
+        let [changed, flagged, reembedded] = ["/tmp/a.txt", "/tmp/b.txt", "/tmp/c.txt"].map(|path| {
+            files
+                .upsert_file(path, "text/plain", "hash", 5, FileSource::Watched, None)
+                .unwrap()
+                .id
+        });
+        files.upsert_content(&changed, "text").unwrap();
+        let jobs = JobRepository::new(&conn);
+        let done = jobs.insert_job(&earlier).unwrap();
+        jobs.update_job_batch(std::slice::from_ref(&done), JobStatus::Completed, None)
+            .unwrap();
+        // The first file's job belongs to an earlier run.
+        conn.execute(
+            "UPDATE jobs SET created_at = datetime('now', '-1 hour'), finished_at = datetime('now', '-1 hour') WHERE id = ?1",
+            [&done],
+        )
+        .unwrap();
+
+        // Changed again while its first job was embedded: counted once, by
+        // the job still pending.
+        let first = jobs.insert_job(&changed).unwrap();
+        jobs.update_job_batch(std::slice::from_ref(&first), JobStatus::Completed, None)
+            .unwrap();
+        conn.execute(
+            "UPDATE jobs SET created_at = datetime('now', '-10 seconds') WHERE id = ?1",
+            [&first],
+        )
+        .unwrap();
+        jobs.insert_job(&changed).unwrap();
+        let skipped = jobs.insert_job(&flagged).unwrap();
+        jobs.update_job_batch(&[skipped], JobStatus::Skipped, Some("secret"))
+            .unwrap();
+        conn.execute(
+            "INSERT INTO jobs (id, file_id, status, kind) VALUES ('reembed', ?1, ?2, ?3)",
+            params![reembedded, JobStatus::Completed, JobKind::Reembed],
+        )
+        .unwrap();
+        let since: String = conn
+            .query_row("SELECT datetime('now', '-1 minute')", [], |row| row.get(0))
+            .unwrap();
+
+        let counts = jobs.run_counts(&since).unwrap();
+        assert_eq!(
+            counts,
+            RunCounts {
+                discovered: 3,
+                hashed: 2,
+                extracted: 1,
+                embedded: 1,
+                skipped: 1,
+                failed: 0,
+                pending: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn job_status_round_trips() {
+        for status in [
+            JobStatus::Pending,
+            JobStatus::Running,
+            JobStatus::Completed,
+            JobStatus::Failed,
+            JobStatus::Skipped,
+        ] {
+            assert_eq!(JobStatus::from_string(status.as_str()), Some(status));
+        }
+        assert_eq!(JobStatus::from_string("any"), None);
+    }
 }
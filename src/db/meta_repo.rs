@@ -0,0 +1,51 @@
+use std::ops::Deref;
+
+use rusqlite::{Connection, params, OptionalExtension, Result};
+
+/// Key/value settings that belong to the database rather than the config file.
+pub struct MetaRepository<C> {
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection>> MetaRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| row.get(0))
+            .optional()
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Sets `key` unless it already has a value. Returns whether it was set.
+    pub fn set_if_absent(&self, key: &str, value: &str) -> Result<bool> {
+        let inserted = self.conn.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO NOTHING",
+            params![key, value],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<()> {
+        self.conn.execute("DELETE FROM meta WHERE key = ?1", [key])?;
+        Ok(())
+    }
+
+    /// Sets `key` to the current UTC time.
+    pub fn set_now(&self, key: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, datetime('now')) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            [key],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::ops::Deref;
+
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use serde::Serialize;
+
+use crate::sync::SyncFile;
+
+/// A node that has synced with this database.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeSummary {
+    pub node_id: String,
+    pub last_seq: i64,
+    pub last_sync_at: String,
+    pub files: usize,
+}
+
+/// Files, chunks and vectors received from other nodes, keyed by
+/// `(node_id, file_id)` so nodes never overwrite each other.
+pub struct NodeRepository<C> {
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection>> NodeRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    pub fn ensure_node(&self, node_id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO sync_nodes (node_id) VALUES (?1) ON CONFLICT(node_id) DO NOTHING",
+            [node_id],
+        )?;
+        Ok(())
+    }
+
+    /// The last change log sequence number applied from `node_id`, 0 for a
+    /// node never heard from.
+    pub fn checkpoint(&self, node_id: &str) -> Result<i64> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT last_seq FROM sync_nodes WHERE node_id = ?1",
+                [node_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0))
+    }
+
+    pub fn set_checkpoint(&self, node_id: &str, last_seq: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sync_nodes SET last_seq = ?2, last_sync_at = CURRENT_TIMESTAMP WHERE node_id = ?1",
+            params![node_id, last_seq],
+        )?;
+        Ok(())
+    }
+
+    /// Replaces what is stored for the file with `file`.
+    pub fn upsert_file(&self, node_id: &str, file: &SyncFile) -> Result<()> {
+        self.conn.execute(
+            r#"
+            INSERT INTO node_files (node_id, file_id, path, file_type, hash, size, source, metadata, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ON CONFLICT(node_id, file_id) DO UPDATE SET
+                path = excluded.path,
+                file_type = excluded.file_type,
+                hash = excluded.hash,
+                size = excluded.size,
+                source = excluded.source,
+                metadata = excluded.metadata,
+                updated_at = excluded.updated_at,
+                received_at = CURRENT_TIMESTAMP
+            "#,
+            params![
+                node_id,
+                &file.file_id,
+                &file.path,
+                &file.file_type,
+                &file.hash,
+                file.size,
+                file.source,
+                file.metadata.as_ref().map(|m| m.to_string()),
+                &file.updated_at,
+            ],
+        )?;
+        self.conn.execute(
+            "DELETE FROM node_chunks WHERE node_id = ?1 AND file_id = ?2",
+            params![node_id, &file.file_id],
+        )?;
+        self.conn.execute(
+            "DELETE FROM node_embeddings WHERE node_id = ?1 AND file_id = ?2",
+            params![node_id, &file.file_id],
+        )?;
+
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO node_chunks (node_id, file_id, chunk_index, content, token_count) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for chunk in &file.chunks {
+            stmt.execute(params![
+                node_id,
+                &file.file_id,
+                chunk.chunk_index,
+                &chunk.content,
+                chunk.token_count
+            ])?;
+        }
+
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO node_embeddings (node_id, file_id, chunk_index, model, dimensions, embedding) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for embedding in &file.embeddings {
+            let vector = serde_json::to_string(&embedding.vector)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            stmt.execute(params![
+                node_id,
+                &file.file_id,
+                embedding.chunk_index,
+                &embedding.model,
+                embedding.dimensions as i64,
+                vector
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Removes a file with its chunks and vectors.
+    pub fn delete_file(&self, node_id: &str, file_id: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM node_files WHERE node_id = ?1 AND file_id = ?2",
+            params![node_id, file_id],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets a node and everything received from it.
+    pub fn delete_node(&self, node_id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM sync_nodes WHERE node_id = ?1", [node_id])?;
+        Ok(())
+    }
+
+    /// Every node heard from, for status reports.
+    pub fn summaries(&self) -> Result<Vec<NodeSummary>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT n.node_id, n.last_seq, n.last_sync_at, COUNT(f.file_id)
+            FROM sync_nodes n
+            LEFT JOIN node_files f ON f.node_id = n.node_id
+            GROUP BY n.node_id
+            ORDER BY n.node_id
+            "#,
+        )?;
+        let nodes = stmt
+            .query_map([], |row| {
+                Ok(NodeSummary {
+                    node_id: row.get(0)?,
+                    last_seq: row.get(1)?,
+                    last_sync_at: row.get(2)?,
+                    files: row.get::<_, i64>(3)? as usize,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(nodes)
+    }
+}
@@ -0,0 +1,122 @@
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+/// How long a connection waits on another connection's write lock before
+/// failing with `database is locked`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long checking a connection out of the [`Pool`] waits for one to be
+/// returned before giving up.
+const CHECKOUT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Opens a connection with the settings every connection to the database
+/// needs. WAL lets readers (including the Python server) keep reading while
+/// the watcher writes; `synchronous = NORMAL` is durable in WAL mode except
+/// for the last transactions before a power loss.
+pub fn open(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    Ok(conn)
+}
+
+/// A fixed set of open connections shared by the watcher, the queue and any
+/// readers. Checking one out blocks until another is returned, or fails with
+/// `SQLITE_BUSY` after [`CHECKOUT_TIMEOUT`].
+pub struct Pool {
+    idle: Mutex<Vec<Connection>>,
+    returned: Condvar,
+}
+
+impl Pool {
+    pub fn new(connections: Vec<Connection>) -> Self {
+        Pool {
+            idle: Mutex::new(connections),
+            returned: Condvar::new(),
+        }
+    }
+
+    pub fn get(&self) -> rusqlite::Result<PooledConnection<'_>> {
+        self.get_within(CHECKOUT_TIMEOUT)
+    }
+
+    fn get_within(&self, timeout: Duration) -> rusqlite::Result<PooledConnection<'_>> {
+        let deadline = Instant::now() + timeout;
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(conn) = idle.pop() {
+                return Ok(PooledConnection {
+                    conn: Some(conn),
+                    pool: self,
+                });
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+                    Some(format!(
+                        "no database connection was free within {:?}",
+                        timeout
+                    )),
+                ));
+            }
+            idle = self
+                .returned
+                .wait_timeout(idle, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+/// A connection checked out of a [`Pool`], returned to it when dropped.
+pub struct PooledConnection<'a> {
+    conn: Option<Connection>,
+    pool: &'a Pool,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection is present until drop")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool
+                .idle
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(conn);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkout_times_out_while_every_connection_is_in_use() {
+        let pool = Pool::new(vec![Connection::open_in_memory().unwrap()]);
+        let held = pool.get().unwrap();
+
+        let started = Instant::now();
+        let err = pool.get_within(Duration::from_millis(50)).err().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(err.sqlite_error_code(), Some(rusqlite::ErrorCode::DatabaseBusy));
+
+        drop(held);
+        assert!(pool.get_within(Duration::from_millis(50)).is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+use std::ops::Deref;
+
+use rusqlite::{Connection, params, Result, Row};
+use uuid::Uuid;
+
+/// Tokens attributed to one job from one embeddings request.
+#[derive(Debug, Clone)]
+pub struct NewUsage<'a> {
+    pub request_id: &'a str,
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub prompt_tokens: i64,
+    pub total_tokens: i64,
+    pub job_id: Option<&'a str>,
+    pub root: Option<&'a str>,
+}
+
+/// Token totals for one group (day, model or root) and model.
+#[derive(Debug, Clone)]
+pub struct UsageSummary {
+    pub group: String,
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub total_tokens: i64,
+}
+
+fn row_to_summary(row: &Row) -> Result<UsageSummary> {
+    Ok(UsageSummary {
+        group: row.get(0)?,
+        model: row.get(1)?,
+        requests: row.get(2)?,
+        prompt_tokens: row.get(3)?,
+        total_tokens: row.get(4)?,
+    })
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum UsageGrouping {
+    Day,
+    Model,
+    Root,
+}
+
+impl UsageGrouping {
+    fn column(self) -> &'static str {
+        match self {
+            UsageGrouping::Day => "date(created_at)",
+            UsageGrouping::Model => "model",
+            UsageGrouping::Root => "COALESCE(root, '(none)')",
+        }
+    }
+}
+
+pub struct UsageRepository<C> {
+    conn: C,
+}
+
+impl<C: Deref<Target = Connection>> UsageRepository<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    pub fn insert_usage(&self, usage: &NewUsage) -> Result<()> {
+        let id = Uuid::new_v4().to_string();
+        self.conn.execute(
+            r#"
+            INSERT INTO usage (id, request_id, provider, model, prompt_tokens, total_tokens, job_id, root)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#,
+            params![
+                &id,
+                usage.request_id,
+                usage.provider,
+                usage.model,
+                usage.prompt_tokens,
+                usage.total_tokens,
+                usage.job_id,
+                usage.root,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Total tokens used since the start of the current (UTC) month.
+    pub fn tokens_this_month(&self) -> Result<u64> {
+        let total: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(total_tokens), 0) FROM usage WHERE created_at >= date('now', 'start of month')",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(total as u64)
+    }
+
+    /// Token totals per group and model, optionally only from `since`
+    /// (a `YYYY-MM-DD` date) onwards.
+    pub fn summarize(&self, grouping: UsageGrouping, since: Option<&str>) -> Result<Vec<UsageSummary>> {
+        let group = grouping.column();
+        let sql = format!(
+            r#"
+            SELECT {group}, model, COUNT(DISTINCT request_id), SUM(prompt_tokens), SUM(total_tokens)
+            FROM usage
+            WHERE ?1 IS NULL OR created_at >= ?1
+            GROUP BY {group}, model
+            ORDER BY {group}, model
+            "#
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map([since], row_to_summary)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+}
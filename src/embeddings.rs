@@ -1,93 +1,661 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
 use serde::Deserialize;
+use tracing::{debug, warn};
 
-#[derive(Debug, Deserialize)]
-pub struct EmbeddingResponse {
-    pub object: String,
-    pub data: Vec<EmbeddingData>,
+use crate::config::{Config, EmbeddingProvider, OversizePolicy};
+use crate::error::BakoError;
+use crate::tokenizer::Tokenizer;
+
+#[cfg(feature = "local-embeddings")]
+mod local;
+mod openai;
+
+/// An embedding model together with the vector size requested from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddingModel {
     pub model: String,
-    pub usage: Usage,
+    pub dimensions: usize,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct EmbeddingData {
-    pub object: String,
-    pub embedding: Vec<f32>,
-    pub index: usize,
+impl std::fmt::Display for EmbeddingModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({} dimensions)", self.model, self.dimensions)
+    }
+}
+
+/// Prefix of the names given to local models, which only the local provider
+/// can run.
+const LOCAL_MODEL_PREFIX: &str = "local/";
+
+/// The model and dimensions the configured provider embeds with, without
+/// connecting to it or loading it.
+pub fn configured_model(config: &Config) -> crate::error::Result<EmbeddingModel> {
+    match config.embedding_provider {
+        EmbeddingProvider::OpenAi => Ok(EmbeddingModel {
+            model: config.embedding_model.clone(),
+            dimensions: config.embedding_dimensions,
+        }),
+        #[cfg(feature = "local-embeddings")]
+        EmbeddingProvider::Local => {
+            let local_config = config.local_model.as_ref().ok_or_else(missing_local_model)?;
+            let path = std::path::Path::new(&local_config.path);
+            Ok(EmbeddingModel {
+                model: local::model_name(path),
+                dimensions: local::model_dimensions(path)?,
+            })
+        }
+        #[cfg(not(feature = "local-embeddings"))]
+        EmbeddingProvider::Local => Err(local_embeddings_disabled()),
+    }
+}
+
+#[cfg(feature = "local-embeddings")]
+fn missing_local_model() -> BakoError {
+    BakoError::Config(
+        "embedding_provider is \"local\" but [local_model] is not configured".to_string(),
+    )
+}
+
+#[cfg(not(feature = "local-embeddings"))]
+fn local_embeddings_disabled() -> BakoError {
+    BakoError::Config(
+        "embedding_provider is \"local\" but bako was built without the `local-embeddings` feature"
+            .to_string(),
+    )
 }
 
+/// Tokens consumed by one provider call.
 #[derive(Debug, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: usize,
     pub total_tokens: usize,
 }
 
+/// Failure reported by (or while talking to) the embeddings provider.
+#[derive(Debug, Clone)]
+pub enum ProviderError {
+    /// 429: the provider asked us to slow down.
+    RateLimited {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// 5xx, 408 or 409: the provider could not serve the request right now.
+    Unavailable {
+        status: u16,
+        message: String,
+    },
+    /// 401/403: the credentials are missing or rejected.
+    Unauthorized {
+        status: u16,
+        message: String,
+    },
+    /// 404: the endpoint or model doesn't exist, so no input can be embedded
+    /// until the configuration is fixed.
+    NotFound {
+        message: String,
+    },
+    /// Any other 4xx: the input itself was rejected and resending it won't help.
+    InvalidInput {
+        status: u16,
+        message: String,
+    },
+    Timeout,
+    Network(String),
+    InvalidResponse(String),
+    /// The on-device model failed to run.
+    #[cfg(feature = "local-embeddings")]
+    Local(String),
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::RateLimited { message, .. } => {
+                write!(f, "Rate limited by provider: {}", message)
+            }
+            ProviderError::Unavailable { status, message } => {
+                write!(f, "Provider unavailable ({}): {}", status, message)
+            }
+            ProviderError::Unauthorized { status, message } => {
+                write!(f, "Provider rejected credentials ({}): {}", status, message)
+            }
+            ProviderError::NotFound { message } => {
+                write!(f, "Provider doesn't know the endpoint or model (404): {}", message)
+            }
+            ProviderError::InvalidInput { status, message } => {
+                write!(f, "Provider rejected input ({}): {}", status, message)
+            }
+            ProviderError::Timeout => write!(f, "Embeddings request timed out"),
+            ProviderError::Network(e) => write!(f, "Failed to send embeddings request: {}", e),
+            ProviderError::InvalidResponse(e) => {
+                write!(f, "Invalid embeddings response: {}", e)
+            }
+            #[cfg(feature = "local-embeddings")]
+            ProviderError::Local(e) => write!(f, "Local embedding model failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl ProviderError {
+    /// Whether the same request may succeed if sent again later.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ProviderError::RateLimited { .. }
+                | ProviderError::Unavailable { .. }
+                | ProviderError::Timeout
+                | ProviderError::Network(_)
+        )
+    }
+
+    /// The HTTP status the provider answered with, if it answered at all.
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            ProviderError::RateLimited { .. } => Some(429),
+            ProviderError::NotFound { .. } => Some(404),
+            ProviderError::Unavailable { status, .. }
+            | ProviderError::Unauthorized { status, .. }
+            | ProviderError::InvalidInput { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether the failure is caused by the input, so the job should be failed
+    /// rather than left pending.
+    pub fn is_permanent_input_error(&self) -> bool {
+        matches!(self, ProviderError::InvalidInput { .. })
+    }
+}
+
+/// Exponential backoff with jitter: a random delay between half and all of
+/// `base * 2^attempt`, capped at `max`.
+fn backoff_delay(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let ceiling = base.saturating_mul(2u32.saturating_pow(attempt)).min(max);
+    let floor = ceiling / 2;
+    rand::rng().random_range(floor..=ceiling)
+}
+
+enum Backend {
+    OpenAi(openai::OpenAiClient),
+    #[cfg(feature = "local-embeddings")]
+    Local(local::LocalModel),
+}
+
 pub struct Embedder {
-    client: reqwest::Client,
+    backend: Backend,
     model: String,
     dimensions: usize,
+    tokenizer: Tokenizer,
+    max_input_tokens: usize,
+    oversize_policy: OversizePolicy,
+    batch_size: usize,
+    batch_max_tokens: usize,
+    max_retries: u32,
+    retry_base_delay: Duration,
+    retry_max_delay: Duration,
+}
+
+/// Splits inputs with the given token counts into consecutive ranges holding
+/// at most `max_inputs` items and, where possible, at most `max_tokens` tokens.
+/// An input that alone exceeds `max_tokens` is placed in a batch of its own.
+pub fn plan_batches(
+    token_counts: &[usize],
+    max_inputs: usize,
+    max_tokens: usize,
+) -> Vec<std::ops::Range<usize>> {
+    let max_inputs = max_inputs.max(1);
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+
+    for (i, &input_tokens) in token_counts.iter().enumerate() {
+        let len = i - start;
+        if len > 0 && (len >= max_inputs || tokens + input_tokens > max_tokens) {
+            batches.push(start..i);
+            start = i;
+            tokens = 0;
+        }
+        tokens += input_tokens;
+    }
+
+    if start < token_counts.len() {
+        batches.push(start..token_counts.len());
+    }
+
+    batches
 }
 
-fn get_openai_api_key() -> std::result::Result<String, Box<dyn std::error::Error>> {
-    std::env::var("OPENAI_API_KEY")
-        .map_err(|_| "Missing OPENAI_API_KEY environment variable".into())
+/// Tokens billed for one embeddings request covering `inputs` (indices into
+/// the slice passed to [`Embedder::embed_many`]).
+#[derive(Debug, Clone)]
+pub struct RequestUsage {
+    pub model: String,
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
+    pub inputs: std::ops::Range<usize>,
+    /// How long the request took, including retries.
+    pub elapsed: Duration,
+}
+
+/// Result of [`Embedder::embed_many`]: one result per input plus the usage of
+/// every request that succeeded.
+#[derive(Debug, Default)]
+pub struct EmbedOutcome {
+    pub results: Vec<std::result::Result<Vec<f32>, ProviderError>>,
+    pub usage: Vec<RequestUsage>,
+}
+
+/// Sends each of `batches` (ranges of `inputs`) with `send` and lines the
+/// vectors up with `inputs`. A batch rejected as invalid is resent one input
+/// at a time so only the offending inputs carry the error; any other failure
+/// applies to the whole batch.
+async fn embed_in_batches<'a, S, F, Fut>(
+    inputs: &'a [S],
+    batches: Vec<std::ops::Range<usize>>,
+    model: &str,
+    send: F,
+) -> EmbedOutcome
+where
+    S: AsRef<str>,
+    F: Fn(Vec<&'a str>) -> Fut,
+    Fut: Future<Output = std::result::Result<(Vec<Vec<f32>>, Usage), ProviderError>>,
+{
+    let request_usage = |usage: Usage, inputs, elapsed| RequestUsage {
+        model: model.to_string(),
+        prompt_tokens: usage.prompt_tokens,
+        total_tokens: usage.total_tokens,
+        inputs,
+        elapsed,
+    };
+    let mut outcome = EmbedOutcome {
+        results: Vec::with_capacity(inputs.len()),
+        usage: Vec::new(),
+    };
+    for range in batches {
+        let batch: Vec<&str> = inputs[range.clone()].iter().map(|s| s.as_ref()).collect();
+        debug!("Sending embeddings request with {} inputs", batch.len());
+        let started = Instant::now();
+        match send(batch.clone()).await {
+            Ok((vectors, usage)) => {
+                outcome.results.extend(vectors.into_iter().map(Ok));
+                outcome
+                    .usage
+                    .push(request_usage(usage, range, started.elapsed()));
+            }
+            Err(e) if e.is_permanent_input_error() && batch.len() > 1 => {
+                warn!(
+                    "Embeddings batch rejected ({}), retrying inputs individually",
+                    e
+                );
+                for (i, input) in range.zip(batch) {
+                    let started = Instant::now();
+                    match send(vec![input]).await {
+                        Ok((mut vectors, usage)) => {
+                            outcome.results.push(Ok(vectors.remove(0)));
+                            outcome
+                                .usage
+                                .push(request_usage(usage, i..i + 1, started.elapsed()));
+                        }
+                        Err(e) => outcome.results.push(Err(e)),
+                    }
+                }
+            }
+            Err(e) => outcome.results.extend(batch.iter().map(|_| Err(e.clone()))),
+        }
+    }
+    outcome
+}
+
+/// A file's text after the oversize policy has been applied.
+#[derive(Debug)]
+pub enum PreparedInput {
+    /// Pieces to embed, each with its token count.
+    Chunks(Vec<(String, usize)>),
+    /// The text won't be embedded, for the given reason.
+    Skipped(String),
 }
 
 impl Embedder {
-    pub async fn new() -> std::result::Result<Self, Box<dyn std::error::Error>> {
-        let api_key = get_openai_api_key()?;
+    pub async fn new(config: &Config) -> crate::error::Result<Self> {
+        Self::build(config, None).await
+    }
 
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(
-            reqwest::header::AUTHORIZATION,
-            format!("Bearer {}", &api_key).parse().unwrap(),
-        );
-        headers.insert(
-            reqwest::header::CONTENT_TYPE,
-            "application/json".parse().unwrap(),
-        );
+    /// An embedder for `model` rather than the configured one, such as the
+    /// model whose vectors search uses while `bako reembed` runs. Only the
+    /// configured local model can be run, but any OpenAI model can be
+    /// requested.
+    pub async fn for_model(config: &Config, model: &EmbeddingModel) -> crate::error::Result<Self> {
+        if model.model.starts_with(LOCAL_MODEL_PREFIX) {
+            if configured_model(config).ok().as_ref() != Some(model) {
+                return Err(BakoError::Config(format!(
+                    "Can't embed with {}: it is no longer the configured local model",
+                    model
+                )));
+            }
+            return Self::new(config).await;
+        }
+        Self::build(config, Some(model)).await
+    }
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()
-            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    /// Builds the configured provider, or an OpenAI client for `openai_model`
+    /// when one is given.
+    async fn build(
+        config: &Config,
+        openai_model: Option<&EmbeddingModel>,
+    ) -> crate::error::Result<Self> {
+        let provider = match openai_model {
+            Some(_) => EmbeddingProvider::OpenAi,
+            None => config.embedding_provider,
+        };
+        let (backend, tokenizer, model, dimensions, max_input_tokens, oversize_policy, batch_size) =
+            match provider {
+                EmbeddingProvider::OpenAi => {
+                    let model = match openai_model {
+                        Some(model) => model.clone(),
+                        None => EmbeddingModel {
+                            model: config.embedding_model.clone(),
+                            dimensions: config.embedding_dimensions,
+                        },
+                    };
+                    (
+                        Backend::OpenAi(openai::OpenAiClient::new(config, &model)?),
+                        Tokenizer::from_name(&config.tokenizer)?,
+                        model.model,
+                        model.dimensions,
+                        config.max_input_tokens,
+                        config.oversize_policy,
+                        config.embedding_batch_size,
+                    )
+                }
+                #[cfg(feature = "local-embeddings")]
+                EmbeddingProvider::Local => {
+                    let local_config =
+                        config.local_model.as_ref().ok_or_else(missing_local_model)?;
+                    let local = local::LocalModel::load(std::path::Path::new(&local_config.path))?;
+                    let max_input_tokens = local_config
+                        .max_input_tokens
+                        .unwrap_or(local.max_input_tokens())
+                        .min(local.max_input_tokens());
+                    let tokenizer = Tokenizer::HuggingFace(local.tokenizer());
+                    let model = local.name().to_string();
+                    let dimensions = local.dimensions();
+                    (
+                        Backend::Local(local),
+                        tokenizer,
+                        model,
+                        dimensions,
+                        max_input_tokens,
+                        local_config
+                            .oversize_policy
+                            .unwrap_or(config.oversize_policy),
+                        local_config.batch_size,
+                    )
+                }
+                #[cfg(not(feature = "local-embeddings"))]
+                EmbeddingProvider::Local => return Err(local_embeddings_disabled()),
+            };
 
         Ok(Embedder {
-            client,
-            model: "text-embedding-3-small".to_string(),
-            dimensions: 512,
+            backend,
+            model,
+            dimensions,
+            tokenizer,
+            max_input_tokens,
+            oversize_policy,
+            batch_size,
+            batch_max_tokens: config.embedding_batch_max_tokens,
+            max_retries: config.embedding_max_retries,
+            retry_base_delay: Duration::from_millis(500),
+            retry_max_delay: Duration::from_secs(60),
+        })
+    }
+
+    /// Measures `text` with the model's tokenizer and applies the oversize
+    /// policy when it doesn't fit in a single input.
+    pub fn prepare(&self, text: &str) -> PreparedInput {
+        if text.trim().is_empty() {
+            return PreparedInput::Skipped("File has no text content".to_string());
+        }
+
+        let tokens = self.tokenizer.count(text);
+        if tokens <= self.max_input_tokens {
+            return PreparedInput::Chunks(vec![(text.to_string(), tokens)]);
+        }
+
+        match self.oversize_policy {
+            OversizePolicy::Truncate => {
+                let truncated = self.tokenizer.truncate(text, self.max_input_tokens);
+                debug!(
+                    "Truncated input from {} to {} tokens",
+                    tokens, self.max_input_tokens
+                );
+                let count = self.tokenizer.count(&truncated);
+                PreparedInput::Chunks(vec![(truncated, count)])
+            }
+            OversizePolicy::Split => PreparedInput::Chunks(
+                self.tokenizer
+                    .split(text, self.max_input_tokens)
+                    .into_iter()
+                    .map(|piece| {
+                        let count = self.tokenizer.count(&piece);
+                        (piece, count)
+                    })
+                    .collect(),
+            ),
+            OversizePolicy::Skip => PreparedInput::Skipped(format!(
+                "Input has {} tokens, more than the limit of {}",
+                tokens, self.max_input_tokens
+            )),
+        }
+    }
+
+    /// Embeds every input, packing them into as few requests as the batch
+    /// limits allow. Returns one result per input, in the same order as
+    /// `inputs`. When a batch is rejected as invalid, its inputs are resent one
+    /// by one so only the offending inputs carry the error.
+    pub async fn embed_many<S: AsRef<str>>(&self, inputs: &[S]) -> EmbedOutcome {
+        let token_counts: Vec<usize> = inputs
+            .iter()
+            .map(|input| self.tokenizer.count(input.as_ref()))
+            .collect();
+        let batches = plan_batches(&token_counts, self.batch_size, self.batch_max_tokens);
+        embed_in_batches(inputs, batches, &self.model, |batch| async move {
+            self.generate_embeddings_with_retry(&batch).await
         })
+        .await
+    }
+
+    pub fn provider_name(&self) -> &'static str {
+        match self.backend {
+            Backend::OpenAi(_) => "openai",
+            #[cfg(feature = "local-embeddings")]
+            Backend::Local(_) => "local",
+        }
+    }
+
+    /// Whether inputs leave the machine to be embedded.
+    pub fn is_remote(&self) -> bool {
+        match self.backend {
+            Backend::OpenAi(_) => true,
+            #[cfg(feature = "local-embeddings")]
+            Backend::Local(_) => false,
+        }
+    }
+
+    pub fn model_info(&self) -> EmbeddingModel {
+        EmbeddingModel {
+            model: self.model.clone(),
+            dimensions: self.dimensions,
+        }
+    }
+
+    /// Like [`Embedder::generate_embeddings`], but retries transient failures
+    /// with jittered exponential backoff, honoring the provider's `Retry-After`
+    /// up to the longest delay between retries.
+    pub async fn generate_embeddings_with_retry(
+        &self,
+        inputs: &[&str],
+    ) -> std::result::Result<(Vec<Vec<f32>>, Usage), ProviderError> {
+        let mut attempt = 0;
+        loop {
+            match self.generate_embeddings(inputs).await {
+                Ok(response) => return Ok(response),
+                Err(e) if e.is_transient() && attempt < self.max_retries => {
+                    let delay = match &e {
+                        ProviderError::RateLimited {
+                            retry_after: Some(retry_after),
+                            ..
+                        } => (*retry_after).min(self.retry_max_delay),
+                        _ => backoff_delay(attempt, self.retry_base_delay, self.retry_max_delay),
+                    };
+                    attempt += 1;
+                    warn!(
+                        "{}; retrying in {:?} (attempt {}/{})",
+                        e, delay, attempt, self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Checks that the provider can be reached without embedding anything.
+    pub async fn check_health(&self) -> std::result::Result<(), ProviderError> {
+        match &self.backend {
+            Backend::OpenAi(client) => client.check_health().await,
+            #[cfg(feature = "local-embeddings")]
+            Backend::Local(_) => Ok(()),
+        }
     }
 
-    pub async fn genereate_embeddings(
+    /// Embeds all `inputs` in a single provider call and returns one vector per
+    /// input, in order, along with the call's token usage.
+    pub async fn generate_embeddings(
         &self,
-        text_content: &str,
-    ) -> std::result::Result<EmbeddingResponse, Box<dyn std::error::Error>> {
-        let res = self
-            .client
-            .post("https://api.openai.com/v1/embeddings")
-            .json(&serde_json::json!({
-                "model": self.model,
-                "input": text_content,
-                "dimensions": self.dimensions,
-            }))
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send embeddings request: {}", e))?;
-
-        if !res.status().is_success() {
-            let status = res.status();
-            let error_text = res
-                .text()
-                .await
-                .unwrap_or_else(|_| "Could not read error response".to_string());
-            return Err(format!("OpenAI API error: {} - {}", status, error_text).into());
-        }
-
-        let embedding_response: EmbeddingResponse = res
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse embeddings response: {}", e))?;
-
-        Ok(embedding_response)
+        inputs: &[&str],
+    ) -> std::result::Result<(Vec<Vec<f32>>, Usage), ProviderError> {
+        match &self.backend {
+            Backend::OpenAi(client) => client.embed(inputs).await,
+            #[cfg(feature = "local-embeddings")]
+            Backend::Local(local) => local.embed(inputs).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_with_jitter_and_stays_under_the_cap() {
+        let base = Duration::from_millis(500);
+        let max = Duration::from_secs(60);
+        let cases = [
+            (0, Duration::from_millis(250), Duration::from_millis(500)),
+            (3, Duration::from_secs(2), Duration::from_secs(4)),
+            (7, Duration::from_secs(30), Duration::from_secs(60)),
+            (40, Duration::from_secs(30), Duration::from_secs(60)),
+        ];
+        for (attempt, low, high) in cases {
+            for _ in 0..100 {
+                let delay = backoff_delay(attempt, base, max);
+                assert!(low <= delay && delay <= high, "attempt {}: {:?}", attempt, delay);
+            }
+        }
+    }
+
+    #[test]
+    fn batches_respect_input_and_token_limits() {
+        assert_eq!(
+            plan_batches(&[1, 1, 1, 1, 1], 2, 100),
+            vec![0..2, 2..4, 4..5]
+        );
+        assert_eq!(plan_batches(&[40, 40, 40, 10], 10, 100), vec![0..2, 2..4]);
+        assert!(plan_batches(&[], 10, 100).is_empty());
+    }
+
+    #[test]
+    fn an_input_over_the_token_limit_gets_a_batch_of_its_own() {
+        assert_eq!(
+            plan_batches(&[10, 500, 10], 10, 100),
+            vec![0..1, 1..2, 2..3]
+        );
+        // A zero input limit still makes progress.
+        assert_eq!(plan_batches(&[1, 1], 0, 100), vec![0..1, 1..2]);
+    }
+
+    /// Embeds each input as its length, failing inputs containing "bad" as
+    /// invalid and whole requests containing "down" as unavailable.
+    async fn fake_send(
+        batch: Vec<&str>,
+    ) -> std::result::Result<(Vec<Vec<f32>>, Usage), ProviderError> {
+        if batch.iter().any(|input| input.contains("down")) {
+            return Err(ProviderError::Unavailable {
+                status: 503,
+                message: "down".to_string(),
+            });
+        }
+        if batch.iter().any(|input| input.contains("bad")) {
+            return Err(ProviderError::InvalidInput {
+                status: 400,
+                message: "bad".to_string(),
+            });
+        }
+        let tokens = batch.len();
+        Ok((
+            batch.iter().map(|input| vec![input.len() as f32]).collect(),
+            Usage {
+                prompt_tokens: tokens,
+                total_tokens: tokens,
+            },
+        ))
+    }
+
+    fn vectors(outcome: &EmbedOutcome) -> Vec<Option<f32>> {
+        outcome
+            .results
+            .iter()
+            .map(|result| result.as_ref().ok().map(|v| v[0]))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn batch_results_line_up_with_inputs() {
+        let inputs = ["a", "bb", "ccc", "dddd", "eeeee"];
+        let outcome = embed_in_batches(&inputs, vec![0..2, 2..5], "m", fake_send).await;
+
+        assert_eq!(
+            vectors(&outcome),
+            vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)]
+        );
+        let ranges: Vec<_> = outcome.usage.iter().map(|u| u.inputs.clone()).collect();
+        assert_eq!(ranges, vec![0..2, 2..5]);
+        assert!(outcome.usage.iter().all(|u| u.model == "m"));
+    }
+
+    #[tokio::test]
+    async fn a_rejected_batch_is_retried_input_by_input() {
+        let inputs = ["a", "bad", "ccc", "down!", "eeeee"];
+        let outcome = embed_in_batches(&inputs, vec![0..3, 3..5], "m", fake_send).await;
+
+        assert_eq!(
+            vectors(&outcome),
+            vec![Some(1.0), None, Some(3.0), None, None]
+        );
+        assert!(
+            outcome.results[1]
+                .as_ref()
+                .unwrap_err()
+                .is_permanent_input_error()
+        );
+        // The unavailable batch fails as a whole, without being split.
+        assert!(outcome.results[4].as_ref().unwrap_err().is_transient());
+        let ranges: Vec<_> = outcome.usage.iter().map(|u| u.inputs.clone()).collect();
+        assert_eq!(ranges, vec![0..1, 2..3]);
     }
 }
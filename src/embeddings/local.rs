@@ -0,0 +1,164 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config as BertConfig};
+use tokenizers::{PaddingParams, Tokenizer as HfTokenizer, TruncationParams};
+use tracing::info;
+
+use super::{ProviderError, Usage};
+use crate::error::{BakoError, Result};
+
+/// Reads the `hidden_size` of the model in `dir`, which is the size of the
+/// vectors it produces.
+pub fn model_dimensions(dir: &Path) -> Result<usize> {
+    Ok(read_config(dir)?.hidden_size)
+}
+
+/// Name recorded on embeddings produced by the model in `dir`.
+pub fn model_name(dir: &Path) -> String {
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| dir.display().to_string());
+    format!("{}{}", super::LOCAL_MODEL_PREFIX, name)
+}
+
+fn read_config(dir: &Path) -> Result<BertConfig> {
+    let path = dir.join("config.json");
+    let data = std::fs::read_to_string(&path).map_err(BakoError::io(&path))?;
+    let config = serde_json::from_str(&data)
+        .map_err(|e| local_error(format!("Failed to parse {}: {}", path.display(), e)))?;
+    Ok(config)
+}
+
+/// A BERT-style sentence-embedding model run on the CPU. Vectors are the
+/// mean of the token embeddings, L2-normalized.
+pub struct LocalModel {
+    model: Arc<BertModel>,
+    /// Pads and truncates batches for inference.
+    batch_tokenizer: Arc<HfTokenizer>,
+    /// Plain tokenizer used to measure and split inputs.
+    tokenizer: Arc<HfTokenizer>,
+    name: String,
+    dimensions: usize,
+    max_position_embeddings: usize,
+}
+
+fn local_error(e: impl std::fmt::Display) -> ProviderError {
+    ProviderError::Local(e.to_string())
+}
+
+impl LocalModel {
+    pub fn load(dir: &Path) -> Result<Self> {
+        info!("Loading local embedding model from {}", dir.display());
+        let config = read_config(dir)?;
+
+        let tokenizer_path = dir.join("tokenizer.json");
+        let tokenizer = HfTokenizer::from_file(&tokenizer_path)
+            .map_err(|e| local_error(format!("Failed to load {}: {}", tokenizer_path.display(), e)))?;
+        let mut batch_tokenizer = tokenizer.clone();
+        batch_tokenizer.with_padding(Some(PaddingParams::default()));
+        batch_tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(|e| local_error(format!("Failed to configure tokenizer: {}", e)))?;
+
+        let weights = dir.join("model.safetensors");
+        // Safety: the weights file is memory-mapped and must not be modified
+        // while bako is running, as with any mmap-based model loader.
+        let load_error = |e| local_error(format!("Failed to load {}: {}", weights.display(), e));
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(&[&weights], DType::F32, &Device::Cpu)
+                .map_err(load_error)?
+        };
+        let model = BertModel::load(vb, &config).map_err(load_error)?;
+
+        Ok(LocalModel {
+            model: Arc::new(model),
+            batch_tokenizer: Arc::new(batch_tokenizer),
+            tokenizer: Arc::new(tokenizer),
+            name: model_name(dir),
+            dimensions: config.hidden_size,
+            max_position_embeddings: config.max_position_embeddings,
+        })
+    }
+
+    pub fn tokenizer(&self) -> Arc<HfTokenizer> {
+        self.tokenizer.clone()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Largest input in tokens, leaving room for the `[CLS]`/`[SEP]` tokens.
+    pub fn max_input_tokens(&self) -> usize {
+        self.max_position_embeddings.saturating_sub(2)
+    }
+
+    /// Runs the model over `inputs` on a blocking thread so inference doesn't
+    /// stall the async runtime.
+    pub async fn embed(&self, inputs: &[&str]) -> Result<(Vec<Vec<f32>>, Usage), ProviderError> {
+        let model = self.model.clone();
+        let tokenizer = self.batch_tokenizer.clone();
+        let inputs: Vec<String> = inputs.iter().map(|s| s.to_string()).collect();
+        tokio::task::spawn_blocking(move || embed_blocking(&model, &tokenizer, inputs))
+            .await
+            .map_err(local_error)?
+    }
+}
+
+fn embed_blocking(
+    model: &BertModel,
+    tokenizer: &HfTokenizer,
+    inputs: Vec<String>,
+) -> Result<(Vec<Vec<f32>>, Usage), ProviderError> {
+    let encodings = tokenizer.encode_batch(inputs, true).map_err(local_error)?;
+    let device = &model.device;
+
+    let ids = encodings
+        .iter()
+        .map(|e| Tensor::new(e.get_ids(), device))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(local_error)?;
+    let masks = encodings
+        .iter()
+        .map(|e| Tensor::new(e.get_attention_mask(), device))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(local_error)?;
+    let tokens: usize = encodings
+        .iter()
+        .map(|e| e.get_attention_mask().iter().filter(|&&m| m == 1).count())
+        .sum();
+
+    let pooled = (|| -> candle_core::Result<Vec<Vec<f32>>> {
+        let ids = Tensor::stack(&ids, 0)?;
+        let mask = Tensor::stack(&masks, 0)?;
+        let type_ids = ids.zeros_like()?;
+        let hidden = model.forward(&ids, &type_ids, Some(&mask))?;
+
+        let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+        let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let counts = mask.sum(1)?;
+        let mean = summed.broadcast_div(&counts)?;
+        let norm = mean.sqr()?.sum_keepdim(1)?.sqrt()?;
+        mean.broadcast_div(&norm)?.to_vec2::<f32>()
+    })()
+    .map_err(local_error)?;
+
+    Ok((
+        pooled,
+        Usage {
+            prompt_tokens: tokens,
+            total_tokens: tokens,
+        },
+    ))
+}
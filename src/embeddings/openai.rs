@@ -0,0 +1,339 @@
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+use tracing::debug;
+
+use super::{EmbeddingModel, ProviderError, Usage};
+use crate::config::Config;
+use crate::error::{BakoError, Result};
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingResponse {
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: Usage,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingData {
+    pub embedding: Vec<f32>,
+    pub index: usize,
+}
+
+impl ProviderError {
+    pub(super) fn from_status(
+        status: reqwest::StatusCode,
+        headers: &reqwest::header::HeaderMap,
+        message: String,
+    ) -> Self {
+        let code = status.as_u16();
+        match code {
+            429 => ProviderError::RateLimited {
+                retry_after: parse_retry_after(headers),
+                message,
+            },
+            401 | 403 => ProviderError::Unauthorized {
+                status: code,
+                message,
+            },
+            404 => ProviderError::NotFound { message },
+            408 | 409 => ProviderError::Unavailable {
+                status: code,
+                message,
+            },
+            _ if status.is_server_error() => ProviderError::Unavailable {
+                status: code,
+                message,
+            },
+            _ => ProviderError::InvalidInput {
+                status: code,
+                message,
+            },
+        }
+    }
+
+    pub(super) fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ProviderError::Timeout
+        } else {
+            ProviderError::Network(e.to_string())
+        }
+    }
+}
+
+/// The longest wait a `retry-after` header is taken at; the server's value is
+/// not trusted beyond it.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Reads `retry-after-ms`, or `retry-after` as either a number of seconds or
+/// an HTTP date, from a response.
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    retry_after_at(headers, SystemTime::now())
+}
+
+fn retry_after_at(headers: &reqwest::header::HeaderMap, now: SystemTime) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(capped_secs(ms / 1000.0));
+    }
+    let value = header("retry-after")?.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return Some(capped_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(now).unwrap_or_default().min(MAX_RETRY_AFTER))
+}
+
+/// `secs` as a duration no longer than [`MAX_RETRY_AFTER`]. Negative and NaN
+/// values mean no wait; values too large for a `Duration` get the maximum.
+fn capped_secs(secs: f64) -> Duration {
+    if secs.is_nan() || secs <= 0.0 {
+        return Duration::ZERO;
+    }
+    Duration::try_from_secs_f64(secs)
+        .unwrap_or(MAX_RETRY_AFTER)
+        .min(MAX_RETRY_AFTER)
+}
+
+/// Puts the vectors of a response for `len` inputs in input order, by the
+/// `index` the provider reports for each.
+fn order_by_index(
+    data: Vec<EmbeddingData>,
+    len: usize,
+) -> std::result::Result<Vec<Vec<f32>>, ProviderError> {
+    let mut vectors: Vec<Option<Vec<f32>>> = vec![None; len];
+    for data in data {
+        let slot = vectors.get_mut(data.index).ok_or_else(|| {
+            ProviderError::InvalidResponse(format!("index {} out of range", data.index))
+        })?;
+        *slot = Some(data.embedding);
+    }
+
+    vectors
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| {
+            v.ok_or_else(|| ProviderError::InvalidResponse(format!("missing index {}", i)))
+        })
+        .collect()
+}
+
+fn get_openai_api_key() -> Result<String> {
+    std::env::var("OPENAI_API_KEY").map_err(|_| {
+        BakoError::Config("Missing OPENAI_API_KEY environment variable".to_string())
+    })
+}
+
+pub struct OpenAiClient {
+    client: reqwest::Client,
+    model: String,
+    dimensions: usize,
+}
+
+impl OpenAiClient {
+    /// A client requesting `model`, with the request settings in `config`.
+    pub fn new(config: &Config, model: &EmbeddingModel) -> Result<Self> {
+        let api_key = get_openai_api_key()?;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", &api_key).parse().unwrap(),
+        );
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            "application/json".parse().unwrap(),
+        );
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.embedding_request_timeout_secs))
+            .build()
+            .map_err(ProviderError::from_reqwest)?;
+
+        Ok(OpenAiClient {
+            client,
+            model: model.model.clone(),
+            dimensions: model.dimensions,
+        })
+    }
+
+    /// Looks up the configured model, which is free and fails the same way an
+    /// embeddings request would when the key is rejected or the API is down.
+    pub async fn check_health(&self) -> std::result::Result<(), ProviderError> {
+        let res = self
+            .client
+            .get(format!("https://api.openai.com/v1/models/{}", self.model))
+            .send()
+            .await
+            .map_err(ProviderError::from_reqwest)?;
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let headers = res.headers().clone();
+            let error_text = res
+                .text()
+                .await
+                .unwrap_or_else(|_| "Could not read error response".to_string());
+            return Err(ProviderError::from_status(status, &headers, error_text));
+        }
+        Ok(())
+    }
+
+    /// Sends all `inputs` in a single embeddings request and returns one vector
+    /// per input, ordered by the `index` the provider reports for each result,
+    /// along with the request's token usage.
+    pub async fn embed(
+        &self,
+        inputs: &[&str],
+    ) -> std::result::Result<(Vec<Vec<f32>>, Usage), ProviderError> {
+        let res = self
+            .client
+            .post("https://api.openai.com/v1/embeddings")
+            .json(&serde_json::json!({
+                "model": self.model,
+                "input": inputs,
+                "dimensions": self.dimensions,
+            }))
+            .send()
+            .await
+            .map_err(ProviderError::from_reqwest)?;
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let headers = res.headers().clone();
+            let error_text = res
+                .text()
+                .await
+                .unwrap_or_else(|_| "Could not read error response".to_string());
+            return Err(ProviderError::from_status(status, &headers, error_text));
+        }
+
+        let embedding_response: EmbeddingResponse = res
+            .json()
+            .await
+            .map_err(|e| ProviderError::InvalidResponse(e.to_string()))?;
+
+        debug!(
+            "Embeddings response from {} used {} prompt tokens ({} total)",
+            embedding_response.model,
+            embedding_response.usage.prompt_tokens,
+            embedding_response.usage.total_tokens
+        );
+
+        let vectors = order_by_index(embedding_response.data, inputs.len())?;
+        Ok((vectors, embedding_response.usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use reqwest::header::HeaderMap;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn classify(status: u16) -> ProviderError {
+        ProviderError::from_status(
+            StatusCode::from_u16(status).unwrap(),
+            &HeaderMap::new(),
+            String::new(),
+        )
+    }
+
+    #[test]
+    fn classifies_statuses() {
+        assert!(matches!(classify(429), ProviderError::RateLimited { .. }));
+        for status in [408, 409, 500, 502, 503] {
+            let e = classify(status);
+            assert!(matches!(e, ProviderError::Unavailable { .. }), "{}", status);
+            assert!(e.is_transient(), "{}", status);
+        }
+        for status in [401, 403] {
+            let e = classify(status);
+            assert!(matches!(e, ProviderError::Unauthorized { .. }), "{}", status);
+            assert!(!e.is_transient(), "{}", status);
+        }
+        // A wrong model name stops the queue instead of failing every job.
+        let e = classify(404);
+        assert!(matches!(e, ProviderError::NotFound { .. }));
+        assert!(!e.is_transient() && !e.is_permanent_input_error());
+        for status in [400, 413, 422] {
+            let e = classify(status);
+            assert!(e.is_permanent_input_error(), "{}", status);
+            assert!(!e.is_transient(), "{}", status);
+        }
+        assert!(classify(429).is_transient());
+    }
+
+    #[test]
+    fn reads_retry_after_in_each_form() {
+        let now = httpdate::parse_http_date("Sun, 18 Oct 2026 12:00:00 GMT").unwrap();
+        let cases = [
+            (headers(&[("retry-after-ms", "1500")]), Some(Duration::from_millis(1500))),
+            (headers(&[("retry-after", "7")]), Some(Duration::from_secs(7))),
+            (
+                headers(&[("retry-after", "Sun, 18 Oct 2026 12:00:30 GMT")]),
+                Some(Duration::from_secs(30)),
+            ),
+            // A date already past means retry right away.
+            (
+                headers(&[("retry-after", "Sun, 18 Oct 2026 11:00:00 GMT")]),
+                Some(Duration::ZERO),
+            ),
+            (
+                headers(&[("retry-after-ms", "250"), ("retry-after", "7")]),
+                Some(Duration::from_millis(250)),
+            ),
+            // Values from the server are capped, however large.
+            (headers(&[("retry-after", "inf")]), Some(MAX_RETRY_AFTER)),
+            (headers(&[("retry-after", "1e300")]), Some(MAX_RETRY_AFTER)),
+            (headers(&[("retry-after-ms", "1e300")]), Some(MAX_RETRY_AFTER)),
+            (headers(&[("retry-after", "86400")]), Some(MAX_RETRY_AFTER)),
+            (
+                headers(&[("retry-after", "Mon, 18 Oct 2027 12:00:00 GMT")]),
+                Some(MAX_RETRY_AFTER),
+            ),
+            (headers(&[("retry-after", "-5")]), Some(Duration::ZERO)),
+            (headers(&[("retry-after", "NaN")]), Some(Duration::ZERO)),
+            (headers(&[("retry-after", "soon")]), None),
+            (HeaderMap::new(), None),
+        ];
+        for (headers, expected) in cases {
+            assert_eq!(retry_after_at(&headers, now), expected, "{:?}", headers);
+        }
+    }
+
+    fn data(index: usize, value: f32) -> EmbeddingData {
+        EmbeddingData {
+            embedding: vec![value],
+            index,
+        }
+    }
+
+    #[test]
+    fn orders_vectors_by_reported_index() {
+        let vectors = order_by_index(vec![data(2, 2.0), data(0, 0.0), data(1, 1.0)], 3).unwrap();
+        assert_eq!(vectors, vec![vec![0.0], vec![1.0], vec![2.0]]);
+    }
+
+    #[test]
+    fn rejects_missing_or_out_of_range_indices() {
+        assert!(matches!(
+            order_by_index(vec![data(0, 0.0)], 2),
+            Err(ProviderError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            order_by_index(vec![data(0, 0.0), data(5, 1.0)], 2),
+            Err(ProviderError::InvalidResponse(_))
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::embeddings::ProviderError;
+
+/// Everything that can go wrong in bako. The variant tells callers what
+/// failed: the job processor uses it to decide between retrying and failing a
+/// job, and the CLI to tell the user what to fix.
+#[derive(Debug, Error)]
+pub enum BakoError {
+    /// The config file is missing or unreadable, or a setting is invalid.
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("I/O error on {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    /// A file's text could not be extracted for embedding.
+    #[error("Failed to extract text from {path}: {reason}")]
+    Extraction { path: String, reason: String },
+    /// The embeddings provider failed; see [`ProviderError::status_code`].
+    #[error(transparent)]
+    Provider(#[from] ProviderError),
+    #[error("File watcher error: {0}")]
+    Watcher(#[from] notify::Error),
+    /// A caller passed something bako can't index or answer, such as an
+    /// empty path.
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    /// The embeddings provider isn't built or reachable, so nothing can be
+    /// embedded right now.
+    #[error("Embeddings provider unavailable: {0}")]
+    ProviderUnavailable(String),
+    /// Exchanging changes with another bako failed.
+    #[error("Sync failed: {0}")]
+    Sync(String),
+    /// An export archive is malformed or in a format this version can't read.
+    #[error("Invalid archive: {0}")]
+    Archive(String),
+    /// An HTTP API request did not carry the API token.
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    /// An HTTP API request came from an address the route doesn't serve.
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+pub type Result<T, E = BakoError> = std::result::Result<T, E>;
+
+impl BakoError {
+    /// For `map_err`: wraps an I/O error with the path it happened on.
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> BakoError {
+        let path = path.into();
+        move |source| BakoError::Io { path, source }
+    }
+
+    /// Whether the operation may succeed if attempted again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            BakoError::Provider(e) => e.is_transient(),
+            BakoError::Database(e) => matches!(
+                e.sqlite_error_code(),
+                Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+            ),
+            BakoError::Io { source, .. } => matches!(
+                source.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            BakoError::ProviderUnavailable(_) | BakoError::Sync(_) => true,
+            BakoError::Config(_)
+            | BakoError::Extraction { .. }
+            | BakoError::Watcher(_)
+            | BakoError::InvalidInput(_)
+            | BakoError::Archive(_)
+            | BakoError::Unauthorized(_)
+            | BakoError::Forbidden(_) => false,
+        }
+    }
+
+    /// What the user can do about the error, for the CLI to print.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            BakoError::Config(_) => {
+                Some("The README lists every setting and where config.toml lives.")
+            }
+            BakoError::Provider(ProviderError::Unauthorized { .. }) => {
+                Some("Check that OPENAI_API_KEY is set and valid.")
+            }
+            BakoError::Provider(ProviderError::NotFound { .. }) => {
+                Some("Check embedding_model and that the provider offers it.")
+            }
+            BakoError::Provider(ProviderError::RateLimited { .. }) => Some(
+                "The provider is rate limiting requests; lower embedding_batch_size or try again later.",
+            ),
+            BakoError::Provider(e) if e.status_code().is_some_and(|status| status >= 500) => {
+                Some("The provider is having problems; pending jobs are retried automatically.")
+            }
+            BakoError::Database(_) if self.is_transient() => {
+                Some("Another process is writing to the database; try again in a moment.")
+            }
+            BakoError::Watcher(_) => Some("Check that watch_directory exists and is readable."),
+            BakoError::ProviderUnavailable(_) => {
+                Some("`bako status` shows the provider's last recorded health.")
+            }
+            BakoError::Sync(_) => Some(
+                "Check that [sync] push_url or pull_url points at a running `bako serve` and that both ends share BAKO_API_TOKEN.",
+            ),
+            BakoError::Unauthorized(_) => {
+                Some("Send the server's BAKO_API_TOKEN as `Authorization: Bearer <token>`.")
+            }
+            _ => None,
+        }
+    }
+}
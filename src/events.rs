@@ -0,0 +1,39 @@
+use crate::progress::Progress;
+
+/// Something that happened while bako indexed files, delivered to
+/// [`Bako::subscribe`](crate::Bako::subscribe) receivers. Events about stored
+/// data are sent after it is committed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BakoEvent {
+    /// A file was recorded (created or changed) and queued for embedding.
+    FileQueued { file_id: String, path: String },
+    /// A file was removed along with its chunks and vectors.
+    FileRemoved { path: String },
+    /// A file's text was embedded and its vectors stored.
+    FileEmbedded {
+        file_id: String,
+        path: String,
+        chunks: usize,
+    },
+    /// A file was deliberately not embedded (oversize, sensitive content,
+    /// path rules, no text).
+    FileSkipped {
+        file_id: String,
+        path: String,
+        reason: String,
+    },
+    /// Embedding a file failed and won't be retried until it changes.
+    FileFailed {
+        file_id: String,
+        path: String,
+        error: String,
+    },
+    /// How far indexing the files queued since the queue was last empty
+    /// has got; sent every few seconds while it changes, and once when the
+    /// queue is empty again.
+    IndexingProgress(Progress),
+    /// The embeddings provider became reachable; pending jobs will drain.
+    ProviderAvailable,
+    /// The embeddings provider can't be used; jobs wait in the queue.
+    ProviderUnavailable { reason: String },
+}
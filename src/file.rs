@@ -1,6 +1,62 @@
 use std::io;
 
-#[derive(Debug)]
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+/// How a file got into the index; stored as its lowercase name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileSource {
+    /// Found in the watched directory.
+    Watched,
+    /// Indexed on request from a path outside the watched directory, or
+    /// imported from an archive.
+    Path,
+    /// Text handed to bako directly; `path` is a name chosen by the caller
+    /// and the stored content is the only copy.
+    Text,
+}
+
+impl FileSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileSource::Watched => "watched",
+            FileSource::Path => "path",
+            FileSource::Text => "text",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Option<FileSource> {
+        match s {
+            "watched" => Some(FileSource::Watched),
+            "path" => Some(FileSource::Path),
+            "text" => Some(FileSource::Text),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl ToSql for FileSource {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for FileSource {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        FileSource::from_string(s)
+            .ok_or_else(|| FromSqlError::Other(format!("Unknown file source: {}", s).into()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct File {
     pub id: String,
     pub path: String,
@@ -9,6 +65,9 @@ pub struct File {
     pub size: i64,
     pub created_at: String,
     pub updated_at: String,
+    pub source: FileSource,
+    /// Caller-supplied JSON object, for files indexed through the API.
+    pub metadata: Option<serde_json::Value>,
 }
 
 impl File {
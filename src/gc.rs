@@ -0,0 +1,117 @@
+//! Garbage collection: pruning completed jobs past their retention and
+//! superseded change log entries, and removing watched files the watcher
+//! never saw go away.
+
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::error::Result;
+use crate::file::FileSource;
+use crate::indexer::Indexer;
+use crate::sync;
+
+/// What one garbage collection removed (or, for a dry run, would remove).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    /// Completed jobs deleted for being older than the retention period.
+    pub jobs: usize,
+    /// Watched files whose path is no longer under the watched directory,
+    /// e.g. after `watch_directory` changed.
+    pub outside_roots: Vec<String>,
+    /// Watched files that no longer exist on disk, e.g. deleted while bako
+    /// wasn't running.
+    pub missing: Vec<String>,
+    /// Change log entries superseded by a later change to the same file,
+    /// up to what the aggregator acknowledged when this node pushes.
+    pub change_log: usize,
+}
+
+impl GcReport {
+    pub fn is_empty(&self) -> bool {
+        self.jobs == 0
+            && self.outside_roots.is_empty()
+            && self.missing.is_empty()
+            && self.change_log == 0
+    }
+}
+
+/// Prunes completed jobs older than `[gc] completed_job_retention_days` and
+/// superseded change log entries, and removes watched files (with their chunks, vectors and jobs) that are
+/// outside the watched directory or gone from disk. Files indexed by path or
+/// as text are left alone, since they are outside the watched directory on
+/// purpose. Nothing is changed with `dry_run`.
+pub(crate) async fn collect_garbage(indexer: &Indexer, dry_run: bool) -> Result<GcReport> {
+    let retention_days = indexer.config.gc.completed_job_retention_days;
+    let jobs = indexer
+        .db
+        .call(move |db| db.jobs()?.delete_completed_older_than(retention_days, dry_run))
+        .await?;
+
+    let files = indexer.db.call(|db| db.files()?.list_files()).await?;
+    // An unmounted or renamed watch directory would make every file look
+    // deleted, so missing files are only collected while it exists.
+    let root_exists = tokio::fs::try_exists(&indexer.config.watch_directory)
+        .await
+        .unwrap_or(false);
+    if !root_exists {
+        warn!(
+            "Watched directory {} doesn't exist; not looking for deleted files",
+            indexer.config.watch_directory
+        );
+    }
+
+    let mut report = GcReport {
+        jobs,
+        ..GcReport::default()
+    };
+    for file in files {
+        if file.source != FileSource::Watched {
+            continue;
+        }
+        if indexer.config.root_for(&file.path).is_none() {
+            report.outside_roots.push(file.path);
+        } else if root_exists && !tokio::fs::try_exists(Path::new(&file.path)).await.unwrap_or(true)
+        {
+            report.missing.push(file.path);
+        }
+    }
+
+    if !dry_run {
+        for path in report.outside_roots.iter().chain(&report.missing) {
+            if let Err(e) = indexer.remove_file(path).await {
+                warn!("Failed to remove {}: {}", path, e);
+            }
+        }
+    }
+    // Entries the aggregator hasn't acknowledged are still to be pushed.
+    // Replicas pulling from this node aren't known here, but pruning only
+    // superseded entries leaves them every file's latest change.
+    let pushing = indexer.config.sync.push_url.is_some();
+    report.change_log = indexer
+        .db
+        .call(move |db| {
+            db.transaction(|tx| {
+                let up_to = if pushing {
+                    sync::load_push_state(tx)?.map_or(0, |state| state.last_seq)
+                } else {
+                    tx.change_log().last_seq()?
+                };
+                tx.change_log().prune_superseded(up_to, dry_run)
+            })
+        })
+        .await?;
+
+    if !report.is_empty() {
+        info!(
+            "Garbage collection{}: {} completed jobs, {} files outside the watched directory, {} deleted files, {} change log entries",
+            if dry_run { " (dry run)" } else { "" },
+            report.jobs,
+            report.outside_roots.len(),
+            report.missing.len(),
+            report.change_log
+        );
+    }
+    Ok(report)
+}
@@ -0,0 +1,458 @@
+//! The HTTP API served by `bako serve`: the same operations as the CLI, as
+//! JSON over HTTP.
+//!
+//! With `BAKO_API_TOKEN` set, every route requires it as a bearer token, but
+//! `/metrics` only does beyond loopback. Without one, the API only listens on
+//! loopback addresses. Requests must name a host the API answers to, so a web
+//! page can't reach it through a DNS name rebound to this machine.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Json, Path, Query, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::error::{BakoError, Result};
+use crate::file::File;
+use crate::search::SearchHit;
+use crate::status::StatusReport;
+use crate::sync::{Checkpoint, SyncBatch};
+use crate::{Bako, IndexReport};
+
+/// Failures are returned as `{"error": ..., "hint": ...}` with a status code
+/// telling callers whether the request or bako was at fault.
+struct ApiError(BakoError);
+
+impl From<BakoError> for ApiError {
+    fn from(e: BakoError) -> Self {
+        ApiError(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            BakoError::InvalidInput(_) | BakoError::Extraction { .. } => StatusCode::BAD_REQUEST,
+            BakoError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            BakoError::Forbidden(_) => StatusCode::FORBIDDEN,
+            BakoError::Io { source, .. } if source.kind() == std::io::ErrorKind::NotFound => {
+                StatusCode::NOT_FOUND
+            }
+            BakoError::Provider(_) | BakoError::ProviderUnavailable(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = serde_json::json!({
+            "error": self.0.to_string(),
+            "hint": self.0.hint(),
+        });
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Environment variable holding the API token. The sync client sends the same
+/// token to the aggregator or peer it talks to.
+pub const API_TOKEN_VAR: &str = "BAKO_API_TOKEN";
+
+/// The API token from [`API_TOKEN_VAR`], if one is set.
+pub fn api_token() -> Option<String> {
+    std::env::var(API_TOKEN_VAR)
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// The `Host` of a request without its port: the URI's authority for HTTP/2,
+/// the `Host` header otherwise.
+fn request_host(request: &Request) -> Option<String> {
+    let host = match request.uri().host() {
+        Some(host) => host,
+        None => request.headers().get(header::HOST)?.to_str().ok()?,
+    };
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?.0,
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    Some(name.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// `localhost` and IP addresses can't be rebound by a web page; any other
+/// name must be in `allowed`.
+fn host_allowed(host: &str, allowed: &[String]) -> bool {
+    host == "localhost"
+        || host.parse::<IpAddr>().is_ok()
+        || allowed.iter().any(|name| name.eq_ignore_ascii_case(host))
+}
+
+async fn require_known_host(
+    State(allowed): State<Arc<[String]>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    match request_host(&request) {
+        Some(host) if host_allowed(&host, &allowed) => Ok(next.run(request).await),
+        Some(host) => Err(BakoError::Forbidden(format!(
+            "{} is not a host this API answers to; add it to http_allowed_hosts",
+            host
+        ))
+        .into()),
+        None => Err(BakoError::Forbidden("missing Host header".to_string()).into()),
+    }
+}
+
+/// Compares in time independent of where the inputs first differ, so the
+/// token can't be guessed a byte at a time.
+fn tokens_match(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn require_token(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if tokens_match(given.as_bytes(), token.as_bytes()) => Ok(next.run(request).await),
+        Some(_) => Err(BakoError::Unauthorized("invalid API token".to_string()).into()),
+        None => Err(BakoError::Unauthorized("missing API token".to_string()).into()),
+    }
+}
+
+/// `/index/path` reads whatever path it is given on this machine, so only
+/// local callers may use it, token or not.
+async fn require_loopback(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !is_loopback(peer.ip()) {
+        return Err(BakoError::Forbidden(format!(
+            "{} only accepts requests from this machine",
+            request.uri().path()
+        ))
+        .into());
+    }
+    Ok(next.run(request).await)
+}
+
+/// Also true for IPv4 loopback addresses mapped into IPv6, as seen by
+/// dual-stack listeners.
+fn is_loopback(ip: IpAddr) -> bool {
+    ip.to_canonical().is_loopback()
+}
+
+/// Refuses to serve the API beyond this machine without a token.
+fn check_exposure(addr: SocketAddr, token: Option<&str>) -> Result<()> {
+    if token.is_none() && !is_loopback(addr.ip()) {
+        return Err(BakoError::Config(format!(
+            "Refusing to serve the HTTP API on {} without {}; set it or listen on a loopback address",
+            addr, API_TOKEN_VAR
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct IndexPathRequest {
+    path: String,
+}
+
+async fn index_path(
+    State(bako): State<Arc<Bako>>,
+    Json(request): Json<IndexPathRequest>,
+) -> Result<Json<IndexReport>, ApiError> {
+    Ok(Json(bako.index_path(&request.path).await?))
+}
+
+#[derive(Deserialize)]
+struct IndexTextRequest {
+    path: String,
+    text: String,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+async fn index_text(
+    State(bako): State<Arc<Bako>>,
+    Json(request): Json<IndexTextRequest>,
+) -> Result<Json<File>, ApiError> {
+    Ok(Json(
+        bako.index_text(&request.path, request.text, request.metadata)
+            .await?,
+    ))
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+async fn search(
+    State(bako): State<Arc<Bako>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchHit>>, ApiError> {
+    Ok(Json(bako.search(&query.q, query.limit).await?))
+}
+
+async fn status(State(bako): State<Arc<Bako>>) -> Result<Json<StatusReport>, ApiError> {
+    Ok(Json(bako.status().await?))
+}
+
+/// `prometheus_client` writes the OpenMetrics flavour of the text format.
+const METRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+async fn metrics(State(bako): State<Arc<Bako>>) -> Result<Response, ApiError> {
+    let body = bako.metrics().await?;
+    Ok(([(axum::http::header::CONTENT_TYPE, METRICS_CONTENT_TYPE)], body).into_response())
+}
+
+async fn node_checkpoint(
+    State(bako): State<Arc<Bako>>,
+    Path(node_id): Path<String>,
+) -> Result<Json<Checkpoint>, ApiError> {
+    Ok(Json(bako.checkpoint(&node_id).await?))
+}
+
+async fn forget_node(
+    State(bako): State<Arc<Bako>>,
+    Path(node_id): Path<String>,
+) -> Result<Json<Checkpoint>, ApiError> {
+    Ok(Json(bako.forget_node(&node_id).await?))
+}
+
+async fn receive_push(
+    State(bako): State<Arc<Bako>>,
+    Json(batch): Json<SyncBatch>,
+) -> Result<Json<Checkpoint>, ApiError> {
+    Ok(Json(bako.receive_batch(batch).await?))
+}
+
+async fn local_node(State(bako): State<Arc<Bako>>) -> Result<Json<Checkpoint>, ApiError> {
+    Ok(Json(bako.local_checkpoint().await?))
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    #[serde(default)]
+    after: i64,
+    #[serde(default = "default_changes_limit")]
+    limit: usize,
+}
+
+fn default_changes_limit() -> usize {
+    200
+}
+
+async fn changes(
+    State(bako): State<Arc<Bako>>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<SyncBatch>, ApiError> {
+    Ok(Json(bako.changes_since(query.after, query.limit).await?))
+}
+
+/// Pushed batches carry vectors, so they are allowed to be much larger than
+/// axum's default limit.
+const PUSH_BODY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// What the API asks of requests: the token, if any, a known `Host`, and
+/// whether `/metrics` needs the token too because it is served beyond
+/// loopback.
+pub struct Access {
+    pub token: Option<String>,
+    pub allowed_hosts: Vec<String>,
+    pub exposed: bool,
+}
+
+impl Access {
+    /// Adds the token and host checks to `routes`, and to `metrics` when
+    /// exposed.
+    fn protect<S>(self, mut routes: Router<S>, mut metrics: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        if let Some(token) = self.token {
+            let token = Arc::<str>::from(token);
+            if self.exposed {
+                metrics = metrics.route_layer(middleware::from_fn_with_state(
+                    Arc::clone(&token),
+                    require_token,
+                ));
+            }
+            routes = routes.route_layer(middleware::from_fn_with_state(token, require_token));
+        }
+        routes.merge(metrics).route_layer(middleware::from_fn_with_state(
+            Arc::<[String]>::from(self.allowed_hosts),
+            require_known_host,
+        ))
+    }
+}
+
+/// The API's routes. Every node serves its change log to replicas; an
+/// aggregator also accepts changes pushed by nodes. The router must be served
+/// with [`SocketAddr`] connect info, which `/index/path` checks.
+pub fn router(bako: Arc<Bako>, aggregator: bool, access: Access) -> Router {
+    let mut api = Router::new()
+        .route(
+            "/index/path",
+            post(index_path).layer(middleware::from_fn(require_loopback)),
+        )
+        .route("/index/text", post(index_text))
+        .route("/search", get(search))
+        .route("/status", get(status))
+        .route("/sync/node", get(local_node))
+        .route("/sync/changes", get(changes));
+    if aggregator {
+        api = api
+            .route(
+                "/sync/nodes/{node_id}",
+                get(node_checkpoint).delete(forget_node),
+            )
+            .route(
+                "/sync/push",
+                post(receive_push).layer(DefaultBodyLimit::max(PUSH_BODY_LIMIT)),
+            );
+    }
+    access
+        .protect(api, Router::new().route("/metrics", get(metrics)))
+        .with_state(bako)
+}
+
+/// A listener [`serve`] or [`serve_metrics`] can serve on, with what it asks
+/// of requests.
+pub struct ApiListener {
+    listener: tokio::net::TcpListener,
+    addr: SocketAddr,
+    access: Access,
+}
+
+/// Binds `listen` for the API or metrics. Fails without `BAKO_API_TOKEN`
+/// unless `listen` is a loopback address.
+pub async fn bind(listen: &str, allowed_hosts: &[String]) -> Result<ApiListener> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(BakoError::io(listen))?;
+    let addr = listener.local_addr().map_err(BakoError::io(listen))?;
+    let token = api_token();
+    check_exposure(addr, token.as_deref())?;
+    Ok(ApiListener {
+        listener,
+        addr,
+        access: Access {
+            token,
+            allowed_hosts: allowed_hosts.to_vec(),
+            exposed: !is_loopback(addr.ip()),
+        },
+    })
+}
+
+/// Serves the API on `listener` until the process exits.
+pub async fn serve(bako: Arc<Bako>, listener: ApiListener, aggregator: bool) -> Result<()> {
+    let ApiListener {
+        listener,
+        addr,
+        access,
+    } = listener;
+    info!(
+        "HTTP API listening on {}{}{}",
+        addr,
+        if aggregator { " (aggregator)" } else { "" },
+        if access.token.is_some() { "" } else { " without an API token" }
+    );
+    let router = router(bako, aggregator, access);
+    axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .map_err(BakoError::io(addr.to_string()))
+}
+
+/// Serves only `/metrics` on `listener`, for bakos that run without the API.
+/// Beyond loopback it requires the token, like the API.
+pub async fn serve_metrics(bako: Arc<Bako>, listener: ApiListener) -> Result<()> {
+    let ApiListener {
+        listener,
+        addr,
+        access,
+    } = listener;
+    info!("Metrics available at http://{}/metrics", addr);
+    let router = access
+        .protect(Router::new(), Router::new().route("/metrics", get(metrics)))
+        .with_state(bako);
+    axum::serve(listener, router)
+        .await
+        .map_err(BakoError::io(addr.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_match_only_when_equal() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secreT", b"secret"));
+        assert!(!tokens_match(b"secret-and-more", b"secret"));
+        assert!(!tokens_match(b"", b"secret"));
+    }
+
+    #[test]
+    fn only_loopback_is_served_without_a_token() {
+        let local: SocketAddr = "127.0.0.1:7733".parse().unwrap();
+        let local_v6: SocketAddr = "[::1]:7733".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:7733".parse().unwrap();
+        let public: SocketAddr = "0.0.0.0:7733".parse().unwrap();
+        assert!(check_exposure(local, None).is_ok());
+        assert!(check_exposure(local_v6, None).is_ok());
+        assert!(check_exposure(mapped, None).is_ok());
+        assert!(matches!(check_exposure(public, None), Err(BakoError::Config(_))));
+        assert!(check_exposure(public, Some("secret")).is_ok());
+    }
+
+    fn host_of(value: &str) -> Option<String> {
+        let request = Request::builder()
+            .uri("/status")
+            .header(header::HOST, value)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        request_host(&request)
+    }
+
+    #[test]
+    fn request_host_drops_the_port() {
+        assert_eq!(host_of("Central:7733").as_deref(), Some("central"));
+        assert_eq!(host_of("central.").as_deref(), Some("central"));
+        assert_eq!(host_of("[::1]:7733").as_deref(), Some("::1"));
+        assert_eq!(host_of("127.0.0.1").as_deref(), Some("127.0.0.1"));
+    }
+
+    #[test]
+    fn only_localhost_addresses_and_listed_names_are_allowed() {
+        let allowed = vec!["Central".to_string()];
+        assert!(host_allowed("localhost", &[]));
+        assert!(host_allowed("127.0.0.1", &[]));
+        assert!(host_allowed("::1", &[]));
+        assert!(host_allowed("central", &allowed));
+        assert!(!host_allowed("central", &[]));
+        assert!(!host_allowed("attacker.example", &allowed));
+    }
+}
@@ -0,0 +1,958 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+use tracing::{Instrument, Span, debug, error, info, info_span, warn};
+
+use crate::config::{Config, PathPolicy};
+use crate::db::chunk_repo::Chunk;
+use crate::db::job_repo::{Job, JobKind, JobStatus};
+use crate::db::{self, Database, Transaction};
+use crate::embeddings::{self, Embedder, EmbeddingModel, ProviderError};
+use crate::error::{BakoError, Result};
+use crate::events::BakoEvent;
+use crate::file::{File, FileSource};
+use crate::metrics::Metrics;
+use crate::privacy::{self, Privacy};
+use crate::{reembed, usage, utils};
+
+/// Reads the text to store with a file, so it can be embedded later even if
+/// the provider is unavailable right now. Files that aren't valid UTF-8 text,
+/// or that path rules never allow to be embedded, have none.
+async fn extract_text(privacy: &Privacy, path: &str) -> Option<String> {
+    if privacy.path_policy(path) == PathPolicy::Never {
+        debug!("Not storing text of {}: excluded by path rules", path);
+        return None;
+    }
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => Some(content),
+        Err(e) => {
+            debug!("No text extracted from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// A file's metadata and text, read from disk (or handed over by the caller)
+/// before it is recorded.
+struct ScannedFile {
+    path: String,
+    file_type: String,
+    hash: String,
+    size: i64,
+    content: Option<String>,
+    source: FileSource,
+    metadata: Option<serde_json::Value>,
+}
+
+async fn scan_file(path: &str, source: FileSource, privacy: &Privacy) -> Result<ScannedFile> {
+    Ok(ScannedFile {
+        path: path.to_string(),
+        file_type: utils::get_file_type(path).map_err(BakoError::io(path))?,
+        hash: utils::hash_file(path).await.map_err(BakoError::io(path))?,
+        size: tokio::fs::metadata(path)
+            .await
+            .map_err(BakoError::io(path))?
+            .len() as i64,
+        content: extract_text(privacy, path).await,
+        source,
+        metadata: None,
+    })
+}
+
+/// Meta key holding when a file change was last recorded.
+const LAST_CHANGE_KEY: &str = "last_file_change_at";
+
+/// When a file was last recorded or removed (UTC), if ever.
+pub(crate) fn last_change_at(tx: &Transaction) -> rusqlite::Result<Option<String>> {
+    tx.meta().get(LAST_CHANGE_KEY)
+}
+
+/// Records a scanned file and queues it for embedding, unless an `index` job
+/// for it is already waiting. With a `skip_reason` the job is closed as
+/// skipped right away.
+fn store_file(
+    tx: &Transaction,
+    scanned: &ScannedFile,
+    skip_reason: Option<&str>,
+) -> rusqlite::Result<File> {
+    let file = tx.files().upsert_file(
+        &scanned.path,
+        &scanned.file_type,
+        &scanned.hash,
+        scanned.size,
+        scanned.source,
+        scanned.metadata.as_ref(),
+    )?;
+    match &scanned.content {
+        Some(content) => tx.files().upsert_content(&file.id, content)?,
+        None => tx.files().delete_content(&file.id)?,
+    }
+    let pending_index_job = tx
+        .jobs()
+        .get_jobs_by_file_id(&file.id, Some(JobStatus::Pending))?
+        .into_iter()
+        .find(|job| job.kind == JobKind::Index);
+    let job_id = match pending_index_job {
+        Some(job) => job.id,
+        None => tx.jobs().insert_job(&file.id)?,
+    };
+    if let Some(reason) = skip_reason {
+        // Drop vectors embedded before the content was flagged.
+        tx.chunks().replace_chunks(&file.id, &[])?;
+        tx.jobs()
+            .update_job_batch(&[job_id], JobStatus::Skipped, Some(reason))?;
+    }
+    tx.meta().set_now(LAST_CHANGE_KEY)?;
+    Ok(file)
+}
+
+/// A pending job together with what the database holds for its file.
+struct QueuedJob {
+    job: Job,
+    file: File,
+    /// The file's current chunks, for `reembed` jobs that can reuse them.
+    stored_chunks: Vec<Chunk>,
+    content: Option<String>,
+}
+
+/// Claims up to `limit` pending jobs for this queue run, marking them running.
+fn claim_jobs(tx: &Transaction, limit: usize) -> rusqlite::Result<Vec<QueuedJob>> {
+    tx.jobs()
+        .claim_jobs(limit)?
+        .into_iter()
+        .map(|job| {
+            let file = tx.files().get_file(&job.file_id)?;
+            let stored_chunks = if job.kind == JobKind::Reembed {
+                tx.chunks().get_chunks(&job.file_id)?
+            } else {
+                Vec::new()
+            };
+            let content = if stored_chunks.is_empty() {
+                tx.files().get_content(&job.file_id)?
+            } else {
+                None
+            };
+            Ok(QueuedJob {
+                job,
+                file,
+                stored_chunks,
+                content,
+            })
+        })
+        .collect()
+}
+
+/// The span a job's log lines are recorded in.
+fn job_span(job: &Job, path: &str) -> Span {
+    info_span!("job", job_id = %job.id, file_id = %job.file_id, path)
+}
+
+struct PreparedJob {
+    job: Job,
+    path: String,
+    root: Option<String>,
+    chunks: Vec<(String, usize)>,
+    /// Ids of `chunks` when they are already stored, so only new vectors
+    /// are written and the file's existing embeddings stay in place.
+    stored_chunk_ids: Option<Vec<String>>,
+}
+
+impl PreparedJob {
+    /// Whether storing the job replaces the file's chunks, which drops the
+    /// vectors every model made of the old ones.
+    fn replaces_chunks(&self) -> bool {
+        self.stored_chunk_ids.is_none()
+    }
+}
+
+/// Vectors from the model search still uses while `bako reembed` runs, for
+/// the jobs that replace their file's chunks, so changed files stay
+/// searchable until the migration is done.
+struct PreviousRun {
+    provider_name: &'static str,
+    model: EmbeddingModel,
+    /// Covers the chunks of the jobs that replace theirs, in job order.
+    outcome: embeddings::EmbedOutcome,
+}
+
+/// What preparing a queued job came to.
+enum Prepared {
+    Ready(Box<PreparedJob>),
+    /// The job was closed as skipped or failed.
+    Closed,
+    /// The file can't be read for now, so the job stays pending.
+    Deferred,
+}
+
+/// How long a job may stay running before it is taken to be left behind by
+/// a run that stopped, and requeued.
+pub(crate) const STALE_JOB_AFTER: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+
+/// How one batch of the queue went.
+struct Batch {
+    /// Whether every job was dealt with, so more may be waiting.
+    more: bool,
+    /// The provider error that left jobs pending, if any.
+    provider_error: Option<ProviderError>,
+}
+
+/// What storing an embeddings run produced.
+struct StoredRun {
+    events: Vec<BakoEvent>,
+    /// The provider error that left jobs pending, if any.
+    provider_error: Option<ProviderError>,
+}
+
+/// Records the usage of the requests that embedded `jobs`' chunks.
+fn record_usage<'a>(
+    tx: &Transaction,
+    provider_name: &str,
+    jobs: impl Iterator<Item = &'a PreparedJob>,
+    requests: &[embeddings::RequestUsage],
+) -> rusqlite::Result<()> {
+    let owners: Vec<usage::InputOwner> = jobs
+        .flat_map(|p| {
+            p.chunks.iter().map(|(_, tokens)| usage::InputOwner {
+                job_id: &p.job.id,
+                root: p.root.as_deref(),
+                tokens: *tokens,
+            })
+        })
+        .collect();
+    for request in requests {
+        usage::record_request_usage(
+            tx,
+            provider_name,
+            request,
+            &owners[request.inputs.clone()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Stores `vectors` from `model` for the chunks `chunk_ids` of a file.
+fn insert_vectors(
+    tx: &Transaction,
+    file_id: &str,
+    chunk_ids: &[String],
+    model: &EmbeddingModel,
+    vectors: Vec<Vec<f32>>,
+) -> rusqlite::Result<()> {
+    for (chunk_id, embedding_vector) in chunk_ids.iter().zip(vectors) {
+        let embedding_json = serde_json::to_string(&embedding_vector)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        tx.embeddings()
+            .insert_embedding(file_id, chunk_id, &model.model, model.dimensions, &embedding_json)?;
+    }
+    Ok(())
+}
+
+/// Records an embeddings run: usage, the new chunks and vectors, and the
+/// jobs' outcome. With `previous`, the replaced chunks also get vectors from
+/// the model being migrated away from.
+fn store_results(
+    tx: &Transaction,
+    provider_name: &str,
+    model: &EmbeddingModel,
+    prepared: Vec<PreparedJob>,
+    outcome: embeddings::EmbedOutcome,
+    previous: Option<PreviousRun>,
+) -> rusqlite::Result<StoredRun> {
+    record_usage(tx, provider_name, prepared.iter(), &outcome.usage)?;
+    let previous = match previous {
+        Some(previous) => {
+            record_usage(
+                tx,
+                previous.provider_name,
+                prepared.iter().filter(|p| p.replaces_chunks()),
+                &previous.outcome.usage,
+            )?;
+            Some((previous.model, previous.outcome.results.into_iter()))
+        }
+        None => None,
+    };
+    let (previous_model, mut previous_results) = previous.unzip();
+
+    let mut results = outcome.results.into_iter();
+    let mut completed_jobs: Vec<Job> = vec![];
+    let mut run = StoredRun {
+        events: Vec::new(),
+        provider_error: None,
+    };
+    for PreparedJob {
+        job,
+        path,
+        chunks,
+        stored_chunk_ids,
+        ..
+    } in prepared
+    {
+        let _span = job_span(&job, &path).entered();
+        // Take all of this job's results before looking at them, so a failed
+        // chunk doesn't leave the rest for the next job.
+        let job_results: Vec<_> = results.by_ref().take(chunks.len()).collect();
+        let previous_vectors: Option<Vec<_>> = match &mut previous_results {
+            Some(results) if stored_chunk_ids.is_none() => {
+                Some(results.by_ref().take(chunks.len()).collect())
+            }
+            _ => None,
+        };
+        match job_results.into_iter().collect::<Result<Vec<Vec<f32>>, _>>() {
+            Ok(vectors) => {
+                let chunk_ids = match stored_chunk_ids {
+                    Some(ids) => ids,
+                    None => tx.chunks().replace_chunks(&job.file_id, &chunks)?,
+                };
+                tx.embeddings()
+                    .delete_for_model(&job.file_id, &model.model, model.dimensions)?;
+                insert_vectors(tx, &job.file_id, &chunk_ids, model, vectors)?;
+                if let (Some(previous), Some(vectors)) = (&previous_model, previous_vectors) {
+                    match vectors.into_iter().collect::<Result<Vec<Vec<f32>>, _>>() {
+                        Ok(vectors) => insert_vectors(tx, &job.file_id, &chunk_ids, previous, vectors)?,
+                        Err(e) => warn!(
+                            "{} is left out of search until re-embedding completes: {}",
+                            path, e
+                        ),
+                    }
+                }
+                run.events.push(BakoEvent::FileEmbedded {
+                    file_id: job.file_id.clone(),
+                    path,
+                    chunks: chunk_ids.len(),
+                });
+                completed_jobs.push(job);
+            }
+            Err(e) if e.is_permanent_input_error() => {
+                error!("Job {} failed permanently: {}", job.id, e);
+                tx.jobs()
+                    .update_job_batch(&[job.id], JobStatus::Failed, Some(&e.to_string()))?;
+                run.events.push(BakoEvent::FileFailed {
+                    file_id: job.file_id,
+                    path,
+                    error: e.to_string(),
+                });
+            }
+            Err(e) => {
+                warn!("Job {} left pending: {}", job.id, e);
+                tx.jobs()
+                    .update_job_batch(&[job.id], JobStatus::Pending, Some(&e.to_string()))?;
+                run.provider_error = Some(e);
+            }
+        }
+    }
+
+    if !completed_jobs.is_empty() {
+        tx.jobs().update_job_batch(
+            &completed_jobs
+                .iter()
+                .map(|job| job.id.clone())
+                .collect::<Vec<_>>(),
+            JobStatus::Completed,
+            None,
+        )?;
+    }
+
+    reembed::complete_if_done(tx, model)?;
+
+    Ok(run)
+}
+
+/// The indexing pipeline: records file changes, then embeds queued files and
+/// stores their vectors. Cheap to clone.
+#[derive(Clone)]
+pub(crate) struct Indexer {
+    pub(crate) db: Database,
+    pub(crate) config: Arc<Config>,
+    privacy: Arc<Privacy>,
+    events: broadcast::Sender<BakoEvent>,
+    pub(crate) metrics: Arc<Metrics>,
+}
+
+impl Indexer {
+    pub fn new(
+        db: Database,
+        config: Arc<Config>,
+        events: broadcast::Sender<BakoEvent>,
+    ) -> Result<Self> {
+        let privacy = Arc::new(Privacy::from_config(&config.privacy)?);
+        Ok(Indexer {
+            db,
+            config,
+            privacy,
+            events,
+            metrics: Arc::new(Metrics::new()),
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BakoEvent> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: BakoEvent) {
+        self.metrics.event(&event);
+        // Sending only fails when nobody is subscribed.
+        let _ = self.events.send(event);
+    }
+
+    pub async fn handle_file_event(&self, event: db::FileEvent) -> Result<()> {
+        info!(
+            "File event received: {} for {}",
+            event.event_type, event.path
+        );
+        self.metrics.file_event(event.event_type);
+
+        let result = match event.event_type {
+            db::FileEventType::Create | db::FileEventType::Modify => self
+                .record_file(&event.path, FileSource::Watched)
+                .await
+                .map(|_| ()),
+            db::FileEventType::Delete => self.remove_file(&event.path).await,
+        };
+        if let Err(e) = &result {
+            error!(
+                "Failed to process {} event for {}: {}",
+                event.event_type, event.path, e
+            );
+        }
+        result
+    }
+
+    /// Hashes a file, stores its text and queues it for embedding.
+    #[tracing::instrument(skip_all, fields(path = path, file_id = tracing::field::Empty))]
+    pub async fn record_file(&self, path: &str, source: FileSource) -> Result<File> {
+        let scanned = scan_file(path, source, &self.privacy).await?;
+        self.store(scanned).await
+    }
+
+    /// Stores `text` under the name `path` and queues it for embedding.
+    #[tracing::instrument(skip_all, fields(path = path, file_id = tracing::field::Empty))]
+    pub async fn record_text(
+        &self,
+        path: &str,
+        text: String,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<File> {
+        let keep_text = self.privacy.path_policy(path) != PathPolicy::Never;
+        let scanned = ScannedFile {
+            path: path.to_string(),
+            file_type: "text/plain".to_string(),
+            hash: utils::hash_text(&text),
+            size: text.len() as i64,
+            content: keep_text.then_some(text),
+            source: FileSource::Text,
+            metadata,
+        };
+        self.store(scanned).await
+    }
+
+    /// Stores the file with its text redacted. Text the privacy filter says
+    /// to skip isn't stored at all, and the file's job is skipped.
+    async fn store(&self, mut scanned: ScannedFile) -> Result<File> {
+        let skip_reason = match scanned.content.take().map(|text| self.privacy.filter(&text)) {
+            Some(privacy::Filtered::Text(text)) => {
+                scanned.content = Some(text);
+                None
+            }
+            Some(privacy::Filtered::Skipped(reason)) => Some(reason),
+            None => None,
+        };
+        let reason = skip_reason.clone();
+        let file = self
+            .db
+            .call(move |db| db.transaction(|tx| store_file(tx, &scanned, reason.as_deref())))
+            .await?;
+        Span::current().record("file_id", file.id.as_str());
+        info!(
+            "Successfully recorded file: {} (ID: {})",
+            file.path, file.id
+        );
+        self.emit(match skip_reason {
+            Some(reason) => {
+                info!("Skipping {}: {}", file.path, reason);
+                BakoEvent::FileSkipped {
+                    file_id: file.id.clone(),
+                    path: file.path.clone(),
+                    reason,
+                }
+            }
+            None => BakoEvent::FileQueued {
+                file_id: file.id.clone(),
+                path: file.path.clone(),
+            },
+        });
+        Ok(file)
+    }
+
+    #[tracing::instrument(skip_all, fields(path = path))]
+    pub async fn remove_file(&self, path: &str) -> Result<()> {
+        let owned_path = path.to_string();
+        self.db
+            .call(move |db| {
+                db.transaction(|tx| {
+                    tx.files().delete_file(&owned_path)?;
+                    tx.meta().set_now(LAST_CHANGE_KEY)
+                })
+            })
+            .await?;
+        self.emit(BakoEvent::FileRemoved {
+            path: path.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Ends a job without embedding it. With `drop_vectors`, embeddings the
+    /// file already has are removed too, for files that may no longer be
+    /// embedded.
+    async fn close_job(
+        &self,
+        job: Job,
+        path: &str,
+        status: JobStatus,
+        message: String,
+        drop_vectors: bool,
+    ) -> Result<()> {
+        let file_id = job.file_id.clone();
+        let stored_message = message.clone();
+        self.db
+            .call(move |db| {
+                db.transaction(|tx| {
+                    if drop_vectors {
+                        tx.chunks().replace_chunks(&job.file_id, &[])?;
+                    }
+                    tx.jobs()
+                        .update_job_batch(&[job.id], status, Some(&stored_message))?;
+                    Ok(())
+                })
+            })
+            .await?;
+        let path = path.to_string();
+        self.emit(match status {
+            JobStatus::Failed => BakoEvent::FileFailed {
+                file_id,
+                path,
+                error: message,
+            },
+            _ => BakoEvent::FileSkipped {
+                file_id,
+                path,
+                reason: message,
+            },
+        });
+        Ok(())
+    }
+
+    /// Puts a claimed job back in the queue for a later run.
+    async fn requeue_job(&self, job: Job, reason: String) -> Result<()> {
+        self.db
+            .call(move |db| {
+                db.jobs()?
+                    .update_job_batch(&[job.id], JobStatus::Pending, Some(&reason))
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Readies one queued job for embedding: its stored chunks, or its text
+    /// read and split. Jobs that can't be embedded are closed here, or left
+    /// pending when the file can't be read for now.
+    async fn prepare_job(
+        &self,
+        queued: QueuedJob,
+        embedder: &Embedder,
+    ) -> Result<Prepared> {
+        let QueuedJob {
+            job,
+            file,
+            stored_chunks,
+            content,
+        } = queued;
+        info!("Processing job: {}", job.id);
+        if let Some(reason) = self.privacy.check_path(&file.path, embedder.is_remote()) {
+            info!("Skipping {} for job {}: {}", file.path, job.id, reason);
+            // Drop vectors embedded before the rule was added.
+            self.close_job(job, &file.path, JobStatus::Skipped, reason, true)
+                .await?;
+            return Ok(Prepared::Closed);
+        }
+        let root = self.config.root_for(&file.path).map(str::to_string);
+        if !stored_chunks.is_empty() {
+            return Ok(Prepared::Ready(Box::new(PreparedJob {
+                job,
+                path: file.path,
+                root,
+                chunks: stored_chunks
+                    .iter()
+                    .map(|c| (c.content.clone(), c.token_count as usize))
+                    .collect(),
+                stored_chunk_ids: Some(stored_chunks.into_iter().map(|c| c.id).collect()),
+            })));
+        }
+        let content = match content {
+            Some(content) => Ok(content),
+            None => file.read().await,
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(e) => {
+                let e = match e.kind() {
+                    std::io::ErrorKind::InvalidData => BakoError::Extraction {
+                        path: file.path.clone(),
+                        reason: "not valid UTF-8 text".to_string(),
+                    },
+                    _ => BakoError::io(&file.path)(e),
+                };
+                if e.is_transient() {
+                    warn!("Job {} left pending: {}", job.id, e);
+                    self.requeue_job(job, e.to_string()).await?;
+                    return Ok(Prepared::Deferred);
+                }
+                error!("Job {} failed: {}", job.id, e);
+                self.close_job(job, &file.path, JobStatus::Failed, e.to_string(), false)
+                    .await?;
+                return Ok(Prepared::Closed);
+            }
+        };
+        let content = match self.privacy.filter(&content) {
+            privacy::Filtered::Text(content) => content,
+            privacy::Filtered::Skipped(reason) => {
+                info!("Skipping {} for job {}: {}", file.path, job.id, reason);
+                self.close_job(job, &file.path, JobStatus::Skipped, reason, true)
+                    .await?;
+                return Ok(Prepared::Closed);
+            }
+        };
+        match embedder.prepare(&content) {
+            embeddings::PreparedInput::Chunks(chunks) => Ok(Prepared::Ready(Box::new(PreparedJob {
+                job,
+                path: file.path,
+                root,
+                chunks,
+                stored_chunk_ids: None,
+            }))),
+            embeddings::PreparedInput::Skipped(reason) => {
+                info!("Skipping {} for job {}: {}", file.path, job.id, reason);
+                self.close_job(job, &file.path, JobStatus::Skipped, reason, false)
+                    .await?;
+                Ok(Prepared::Closed)
+            }
+        }
+    }
+
+    /// Embeds pending jobs, `queue_batch_size` at a time and no more than the
+    /// monthly token budget leaves room for. While `bako reembed` runs,
+    /// `previous` is the model search still uses: files whose chunks change
+    /// are embedded with it too, so they stay searchable. Jobs left running
+    /// for longer than [`STALE_JOB_AFTER`] are requeued first. Returns the
+    /// provider error that left jobs pending, if any, so the caller can stop
+    /// sending work until the provider recovers.
+    pub async fn process_queue(
+        &self,
+        embedder: &Embedder,
+        previous: Option<&Embedder>,
+    ) -> Result<Option<ProviderError>> {
+        let requeued = self
+            .db
+            .call(|db| db.jobs()?.requeue_stale(STALE_JOB_AFTER))
+            .await?;
+        if requeued > 0 {
+            info!("Requeued {} jobs left running by a run that stopped", requeued);
+        }
+        let queue_size = self.db.call(|db| db.jobs()?.get_queue_size()).await?;
+        let batch_size = self.config.queue_batch_size.max(1);
+        info!(
+            "Processing event queue (queue size: {}, batch size: {})",
+            queue_size, batch_size
+        );
+
+        loop {
+            let budget_left = match self.config.monthly_token_budget {
+                Some(budget) => {
+                    let used = self.db.call(|db| db.usage()?.tokens_this_month()).await?;
+                    if used >= budget {
+                        warn!(
+                            "Monthly token budget exhausted ({} of {} tokens used); queue paused",
+                            used, budget
+                        );
+                        return Ok(None);
+                    }
+                    Some(budget - used)
+                }
+                None => None,
+            };
+
+            let jobs = self
+                .db
+                .call(move |db| db.transaction(|tx| claim_jobs(tx, batch_size)))
+                .await?;
+            if jobs.is_empty() {
+                return Ok(None);
+            }
+            // Jobs this batch puts back in the queue would only be claimed
+            // again, so the run ends with it unless every job was dealt with.
+            let full = jobs.len() == batch_size;
+            let claimed: Vec<String> = jobs.iter().map(|queued| queued.job.id.clone()).collect();
+            let batch = match self.process_batch(jobs, embedder, previous, budget_left).await {
+                Ok(batch) => batch,
+                Err(e) => {
+                    // Whatever the batch hadn't dealt with yet goes back in the
+                    // queue rather than staying claimed.
+                    let released = self
+                        .db
+                        .call(move |db| db.jobs()?.release_jobs(&claimed))
+                        .await;
+                    match released {
+                        Ok(released) if released > 0 => {
+                            warn!("Requeued {} jobs after the queue run failed", released)
+                        }
+                        Ok(_) => {}
+                        Err(release_error) => {
+                            error!("Failed to requeue claimed jobs: {}", release_error)
+                        }
+                    }
+                    return Err(e);
+                }
+            };
+            if batch.provider_error.is_some() || !(full && batch.more) {
+                return Ok(batch.provider_error);
+            }
+        }
+    }
+
+    /// Prepares, embeds and stores one batch of claimed jobs, closing or
+    /// requeueing every one of them unless it fails partway.
+    async fn process_batch(
+        &self,
+        jobs: Vec<QueuedJob>,
+        embedder: &Embedder,
+        previous: Option<&Embedder>,
+        mut budget_left: Option<u64>,
+    ) -> Result<Batch> {
+        let mut batch = Batch {
+            more: true,
+            provider_error: None,
+        };
+        let mut prepared: Vec<PreparedJob> = Vec::with_capacity(jobs.len());
+        for queued in jobs {
+            let span = job_span(&queued.job, &queued.file.path);
+            let job = match self.prepare_job(queued, embedder).instrument(span).await? {
+                Prepared::Ready(job) => *job,
+                Prepared::Closed => continue,
+                Prepared::Deferred => {
+                    batch.more = false;
+                    continue;
+                }
+            };
+            let mut tokens: u64 = job.chunks.iter().map(|(_, tokens)| *tokens as u64).sum();
+            if previous.is_some() && job.replaces_chunks() {
+                tokens *= 2;
+            }
+            match &mut budget_left {
+                Some(left) if tokens > *left => {
+                    let reason = format!(
+                        "its {} tokens exceed the {} left in this month's budget",
+                        tokens, left
+                    );
+                    info!("Job {} left pending: {}", job.job.id, reason);
+                    self.requeue_job(job.job, reason).await?;
+                    batch.more = false;
+                }
+                Some(left) => {
+                    *left -= tokens;
+                    prepared.push(job);
+                }
+                None => prepared.push(job),
+            }
+        }
+        if prepared.is_empty() {
+            return Ok(batch);
+        }
+
+        let inputs: Vec<&str> = prepared
+            .iter()
+            .flat_map(|p| p.chunks.iter().map(|(text, _)| text.as_str()))
+            .collect();
+        let outcome = embedder.embed_many(&inputs).await;
+
+        let provider_name = embedder.provider_name();
+        for usage in &outcome.usage {
+            self.metrics.embedding_request(provider_name, usage);
+        }
+        let previous = match previous {
+            Some(previous) => {
+                let inputs: Vec<&str> = prepared
+                    .iter()
+                    .filter(|p| p.replaces_chunks())
+                    .flat_map(|p| p.chunks.iter().map(|(text, _)| text.as_str()))
+                    .collect();
+                let outcome = previous.embed_many(&inputs).await;
+                for usage in &outcome.usage {
+                    self.metrics.embedding_request(previous.provider_name(), usage);
+                }
+                Some(PreviousRun {
+                    provider_name: previous.provider_name(),
+                    model: previous.model_info(),
+                    outcome,
+                })
+            }
+            None => None,
+        };
+        let model = embedder.model_info();
+        let run = self
+            .db
+            .call(move |db| {
+                db.transaction(|tx| {
+                    store_results(tx, provider_name, &model, prepared, outcome, previous)
+                })
+            })
+            .await?;
+        for event in run.events {
+            self.emit(event);
+        }
+        batch.provider_error = run.provider_error;
+        Ok(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::temp_db;
+
+    fn queue_file(tx: &Transaction, path: &str) -> rusqlite::Result<Job> {
+        let file = tx
+            .files()
+            .upsert_file(path, "text/plain", path, 1, FileSource::Watched, None)?;
+        tx.jobs().insert_job(&file.id)?;
+        Ok(tx.jobs().get_jobs_by_file_id(&file.id, None)?.remove(0))
+    }
+
+    fn prepared(job: Job, path: &str, chunks: usize) -> PreparedJob {
+        PreparedJob {
+            job,
+            path: path.to_string(),
+            root: None,
+            chunks: (0..chunks).map(|i| (format!("{} {}", path, i), 1)).collect(),
+            stored_chunk_ids: None,
+        }
+    }
+
+    #[test]
+    fn a_failed_job_does_not_shift_the_next_jobs_vectors() {
+        let db = temp_db();
+        let model = EmbeddingModel {
+            model: "m".to_string(),
+            dimensions: 1,
+        };
+        let run = db
+            .transaction(|tx| {
+                let first = queue_file(tx, "/a.txt")?;
+                let second = queue_file(tx, "/b.txt")?;
+                let outcome = embeddings::EmbedOutcome {
+                    results: vec![
+                        Err(ProviderError::InvalidInput {
+                            status: 400,
+                            message: "too long".to_string(),
+                        }),
+                        Ok(vec![1.0]),
+                        Ok(vec![2.0]),
+                        Ok(vec![3.0]),
+                    ],
+                    usage: Vec::new(),
+                };
+                store_results(
+                    tx,
+                    "test",
+                    &model,
+                    vec![prepared(first, "/a.txt", 2), prepared(second, "/b.txt", 2)],
+                    outcome,
+                    None,
+                )
+            })
+            .unwrap();
+        assert!(run.provider_error.is_none());
+
+        let files = db.files().unwrap();
+        let a = files.find_by_path("/a.txt").unwrap().unwrap();
+        let b = files.find_by_path("/b.txt").unwrap().unwrap();
+        let jobs = db.jobs().unwrap();
+        assert_eq!(jobs.get_jobs_by_file_id(&a.id, None).unwrap()[0].status, JobStatus::Failed);
+        assert_eq!(jobs.get_jobs_by_file_id(&b.id, None).unwrap()[0].status, JobStatus::Completed);
+
+        let embeddings = db.embeddings().unwrap();
+        assert!(embeddings.get_for_file(&a.id).unwrap().is_empty());
+        let mut vectors: Vec<String> = embeddings
+            .get_for_file(&b.id)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.embedding)
+            .collect();
+        vectors.sort();
+        assert_eq!(vectors, vec!["[2.0]", "[3.0]"]);
+    }
+
+    #[test]
+    fn changed_files_keep_vectors_from_the_previous_model_during_a_migration() {
+        let db = temp_db();
+        let target = EmbeddingModel {
+            model: "new".to_string(),
+            dimensions: 1,
+        };
+        let previous = EmbeddingModel {
+            model: "old".to_string(),
+            dimensions: 1,
+        };
+        let (changed, unchanged) = db
+            .transaction(|tx| {
+                let changed = queue_file(tx, "/changed.txt")?;
+                let unchanged = queue_file(tx, "/unchanged.txt")?;
+                let chunk_ids = tx
+                    .chunks()
+                    .replace_chunks(&unchanged.file_id, &[("kept".to_string(), 1)])?;
+                tx.embeddings()
+                    .insert_embedding(&unchanged.file_id, &chunk_ids[0], "old", 1, "[9.0]")?;
+                let mut kept = prepared(unchanged.clone(), "/unchanged.txt", 1);
+                kept.stored_chunk_ids = Some(chunk_ids);
+                let outcome = |results: Vec<Vec<f32>>| embeddings::EmbedOutcome {
+                    results: results.into_iter().map(Ok).collect(),
+                    usage: Vec::new(),
+                };
+                store_results(
+                    tx,
+                    "test",
+                    &target,
+                    vec![prepared(changed.clone(), "/changed.txt", 1), kept],
+                    outcome(vec![vec![1.0], vec![2.0]]),
+                    Some(PreviousRun {
+                        provider_name: "test",
+                        model: previous.clone(),
+                        // Only the changed file's chunk is embedded again.
+                        outcome: outcome(vec![vec![-1.0]]),
+                    }),
+                )?;
+                Ok((changed, unchanged))
+            })
+            .unwrap();
+
+        let vectors = |file_id: &str| {
+            let mut vectors: Vec<(String, String)> = db
+                .embeddings()
+                .unwrap()
+                .get_for_file(file_id)
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.model, e.embedding))
+                .collect();
+            vectors.sort();
+            vectors
+        };
+        let pair = |model: &str, vector: &str| (model.to_string(), vector.to_string());
+        assert_eq!(
+            vectors(&changed.file_id),
+            vec![pair("new", "[1.0]"), pair("old", "[-1.0]")]
+        );
+        assert_eq!(
+            vectors(&unchanged.file_id),
+            vec![pair("new", "[2.0]"), pair("old", "[9.0]")]
+        );
+    }
+}
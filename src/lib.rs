@@ -0,0 +1,669 @@
+//! Bako watches a directory, embeds the text of the files in it and keeps
+//! the vectors in a local SQLite database for semantic search.
+//!
+//! [`Bako`] is the entry point for embedding bako in other programs: it opens
+//! the database, records files, embeds them and searches the result. The
+//! `bako` binary is a thin wrapper around it.
+//!
+//! ```no_run
+//! # async fn example() -> bako::Result<()> {
+//! let config = bako::Config::load_or_init().await?;
+//! let bako = bako::Bako::open(config).await?;
+//! bako.index_path("/notes/todo.md").await?;
+//! bako.process_queue().await?;
+//! for hit in bako.search("what is left to do", 5).await? {
+//!     println!("{:.3} {}", hit.score, hit.path);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, broadcast};
+use tracing::{debug, error, info, warn};
+
+pub mod archive;
+pub mod config;
+pub mod db;
+pub mod embeddings;
+pub mod error;
+pub mod events;
+pub mod file;
+pub mod gc;
+pub mod http;
+pub mod logging;
+pub mod maintenance;
+pub mod metrics;
+pub mod progress;
+pub mod provider;
+pub mod reembed;
+pub mod search;
+pub mod status;
+pub mod sync;
+pub mod usage;
+mod indexer;
+mod privacy;
+mod tokenizer;
+mod utils;
+mod watcher;
+
+pub use config::Config;
+pub use error::{BakoError, Result};
+pub use events::BakoEvent;
+pub use search::SearchHit;
+
+use db::Database;
+use db::usage_repo::{UsageGrouping, UsageSummary};
+use file::{File, FileSource};
+use indexer::Indexer;
+use provider::{ProviderHealth, ProviderState};
+use serde::Serialize;
+
+/// How many events a subscriber may fall behind before it misses some.
+const EVENT_CAPACITY: usize = 256;
+
+/// How long to wait before retrying a scheduled backup that failed.
+const BACKUP_RETRY: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// What [`Bako::index_path`] recorded.
+#[derive(Debug, Serialize)]
+pub struct IndexReport {
+    /// Files recorded and queued for embedding.
+    pub files: Vec<File>,
+    /// Files under a directory that could not be read, with the reason.
+    pub failed: Vec<IndexFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// A bako index: its database, the embeddings provider and the indexing
+/// pipeline. Methods take `&self`, so one `Bako` can be shared (e.g. in an
+/// `Arc`) between the watcher loop and callers that search it.
+pub struct Bako {
+    indexer: Indexer,
+    provider: Mutex<ProviderState>,
+    /// For talking to other bako instances.
+    http: reqwest::Client,
+}
+
+impl Bako {
+    /// Opens (creating and migrating if needed) the database at
+    /// `config.db_path`. The embeddings provider is only built when first
+    /// needed, so a missing API key or model doesn't prevent opening.
+    pub async fn open(config: Config) -> Result<Bako> {
+        let db_path = Path::new(&config.db_path);
+        info!("Initializing database at {}", db_path.display());
+        let db = Database::new(db_path)?;
+        match embeddings::configured_model(&config) {
+            Ok(model) => {
+                db.call(move |db| db.transaction(|tx| reembed::check_embedding_config(tx, &model)))
+                    .await?
+            }
+            Err(e) => warn!("Could not determine the configured embedding model: {}", e),
+        }
+
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let indexer = Indexer::new(db, Arc::new(config), events.clone())?;
+        Ok(Bako {
+            indexer,
+            provider: Mutex::new(ProviderState::new(events)),
+            http: reqwest::Client::new(),
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.indexer.config
+    }
+
+    /// The underlying database, for queries the facade doesn't cover.
+    pub fn database(&self) -> &Database {
+        &self.indexer.db
+    }
+
+    /// This index's node id, generated when the database was created.
+    pub fn node_id(&self) -> &str {
+        self.indexer.db.node_id()
+    }
+
+    /// Receives an event for every file recorded, embedded, skipped, failed
+    /// or removed from now on, for changes in provider health, and, while
+    /// [`Bako::run`] works through queued files, for their progress.
+    pub fn subscribe(&self) -> broadcast::Receiver<BakoEvent> {
+        self.indexer.subscribe()
+    }
+
+    /// Records a file (hash, size, text) and queues it for embedding, as if
+    /// it had changed in the watched directory. The path may be outside the
+    /// watched directory; a directory is indexed recursively, once, without
+    /// being watched. Files already indexed are updated in place.
+    pub async fn index_path(&self, path: impl AsRef<Path>) -> Result<IndexReport> {
+        let path = std::path::absolute(path.as_ref()).map_err(BakoError::io(path.as_ref()))?;
+        let is_dir = tokio::fs::metadata(&path)
+            .await
+            .map_err(BakoError::io(&path))?
+            .is_dir();
+        if !is_dir {
+            let file = self.index_file(&path).await?;
+            return Ok(IndexReport {
+                files: vec![file],
+                failed: Vec::new(),
+            });
+        }
+
+        let mut report = IndexReport {
+            files: Vec::new(),
+            failed: Vec::new(),
+        };
+        for path in utils::list_files(&path).await.map_err(BakoError::io(&path))? {
+            match self.index_file(&path).await {
+                Ok(file) => report.files.push(file),
+                Err(e) => {
+                    warn!("Not indexing {}: {}", path.display(), e);
+                    report.failed.push(IndexFailure {
+                        path: path.display().to_string(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        info!(
+            "Indexed {} files under {} ({} failed)",
+            report.files.len(),
+            path.display(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    async fn index_file(&self, path: &Path) -> Result<File> {
+        let path = path.to_str().ok_or_else(|| {
+            BakoError::InvalidInput(format!("{} is not valid UTF-8", path.display()))
+        })?;
+        let source = match self.config().root_for(path) {
+            Some(_) => FileSource::Watched,
+            None => FileSource::Path,
+        };
+        self.indexer.record_file(path, source).await
+    }
+
+    /// Stores `text` under `path` and queues it for embedding. `path` is
+    /// only a name (e.g. `notes://standup/2025-06-02`): nothing is read from
+    /// disk, and indexing the same path again replaces the text. `metadata`,
+    /// if given, must be a JSON object; it is stored with the file and
+    /// returned with search hits.
+    pub async fn index_text(
+        &self,
+        path: &str,
+        text: impl Into<String>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<File> {
+        if path.trim().is_empty() {
+            return Err(BakoError::InvalidInput("the path is empty".to_string()));
+        }
+        if metadata.as_ref().is_some_and(|m| !m.is_object()) {
+            return Err(BakoError::InvalidInput(
+                "metadata must be a JSON object".to_string(),
+            ));
+        }
+        self.indexer.record_text(path, text.into(), metadata).await
+    }
+
+    /// Embeds the files waiting in the queue. Returns `false` without doing
+    /// anything when the embeddings provider isn't usable; jobs it fails on
+    /// stay queued for the next run.
+    pub async fn process_queue(&self) -> Result<bool> {
+        let (active, target, queued) = self
+            .indexer
+            .db
+            .call(|db| {
+                db.snapshot(|tx| {
+                    Ok((
+                        reembed::active_model(tx)?,
+                        reembed::target_model(tx)?,
+                        tx.jobs().get_queue_size()?,
+                    ))
+                })
+            })
+            .await?;
+        // New vectors come from the active model until `bako reembed` starts
+        // a migration, so search can find them; from then on they come from
+        // the target, and changed files are embedded with both.
+        let (embedder, previous) = match target {
+            Some(target) => (self.embedder_for(Some(target)).await, active),
+            None => (self.embedder_for(active).await, None),
+        };
+        let embedder = match embedder {
+            Ok(embedder) => embedder,
+            Err(e @ BakoError::Config(_)) => {
+                warn!("{}; leaving the queue for later", e);
+                return Ok(false);
+            }
+            Err(e) => {
+                debug!("{}; leaving the queue for later", e);
+                return Ok(false);
+            }
+        };
+        let previous = match previous {
+            Some(model) => match self.embedder_for(Some(model)).await {
+                Ok(previous) => Some(previous),
+                Err(e) if queued > 0 => {
+                    warn!(
+                        "Changed files are left out of search until re-embedding completes: {}",
+                        e
+                    );
+                    None
+                }
+                Err(_) => None,
+            },
+            None => None,
+        };
+        match self
+            .indexer
+            .process_queue(&embedder, previous.as_deref())
+            .await?
+        {
+            Some(e) => {
+                self.provider
+                    .lock()
+                    .await
+                    .mark_unavailable(&self.indexer.db, e.to_string())
+                    .await;
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Returns the `limit` indexed chunks most similar to `query`, best
+    /// first. Only vectors from the active model are searched, so while
+    /// `bako reembed` runs the query is embedded with the model being
+    /// replaced.
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let active = self
+            .indexer
+            .db
+            .call(|db| db.snapshot(reembed::active_model))
+            .await?;
+        let embedder = self.embedder_for(active).await?;
+        search::search(&self.indexer.db, &embedder, query, limit).await
+    }
+
+    /// Watches `watch_directory`, recording changes as they happen and
+    /// processing the queue every `queue_process_interval_secs`. With
+    /// `[sync] push_url` or `pull_url` set, also pushes changes to the
+    /// aggregator or mirrors the peer every `interval_secs`, and with
+    /// `[backup] directory` set, backs up the database every
+    /// `interval_hours`. Collects garbage every `[gc] interval_hours`, and
+    /// sends [`BakoEvent::IndexingProgress`] while files are being indexed.
+    /// Runs until the watcher stops.
+    pub async fn run(&self) -> Result<()> {
+        tokio::select! {
+            result = self.watch() => result,
+            _ = self.sync_periodically() => Ok(()),
+            _ = self.backup_periodically() => Ok(()),
+            _ = self.collect_garbage_periodically() => Ok(()),
+            _ = progress::report(&self.indexer) => Ok(()),
+        }
+    }
+
+    async fn watch(&self) -> Result<()> {
+        let config = self.config();
+        let target_dir = Path::new(&config.watch_directory);
+        let mut fs_event_receiver =
+            watcher::setup_file_watcher(
+                target_dir,
+                config.watcher_poll_duration_secs,
+                Arc::clone(&self.indexer.metrics),
+            )?;
+
+        info!(
+            "Starting queue-based event processing (interval: {}s, batch size: {})",
+            config.queue_process_interval_secs, config.queue_batch_size
+        );
+        let process_interval = std::time::Duration::from_secs(config.queue_process_interval_secs);
+        let mut interval = tokio::time::interval(process_interval);
+        loop {
+            tokio::select! {
+                Some(event) = fs_event_receiver.recv() => {
+                    debug!("Received file system event: {} for {}", event.event_type, event.path);
+                    if let Err(e) = self.indexer.handle_file_event(event).await {
+                        error!("Error handling event: {:?}", e);
+                    }
+                }
+
+                _ = interval.tick() => {
+                    if let Err(e) = self.process_queue().await {
+                        error!("Error processing event queue: {}", e);
+                    }
+                }
+
+                else => {
+                    info!("All channels closed, exiting main loop");
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pushes the changes the aggregator at `[sync] push_url` hasn't seen.
+    pub async fn push_changes(&self) -> Result<sync::PushReport> {
+        sync::push(&self.indexer.db, &self.http, &self.config().sync).await
+    }
+
+    /// Mirrors the peer at `[sync] pull_url`: fetches its changes since the
+    /// last pull. Its files become searchable here but are never re-hashed
+    /// or re-embedded.
+    pub async fn pull_changes(&self) -> Result<sync::PullReport> {
+        sync::pull(&self.indexer.db, &self.http, &self.config().sync).await
+    }
+
+    async fn sync_periodically(&self) {
+        let sync = &self.config().sync;
+        if sync.push_url.is_none() && sync.pull_url.is_none() {
+            return std::future::pending().await;
+        }
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(sync.interval_secs));
+        loop {
+            interval.tick().await;
+            if sync.push_url.is_some()
+                && let Err(e) = self.push_changes().await
+            {
+                warn!("Failed to push changes: {}", e);
+            }
+            if sync.pull_url.is_some()
+                && let Err(e) = self.pull_changes().await
+            {
+                warn!("Failed to pull changes: {}", e);
+            }
+        }
+    }
+
+    async fn backup_periodically(&self) {
+        let config = &self.config().backup;
+        if config.directory.is_none() {
+            return std::future::pending().await;
+        }
+        loop {
+            tokio::time::sleep(maintenance::next_backup_in(config)).await;
+            let db = self.indexer.db.clone();
+            let backup = config.clone();
+            if let Err(e) = blocking(move || maintenance::scheduled_backup(&db, &backup)).await {
+                warn!("Scheduled backup failed: {}", e);
+                tokio::time::sleep(BACKUP_RETRY).await;
+            }
+        }
+    }
+
+    /// Deletes completed jobs past their retention and superseded change log
+    /// entries, and removes watched files that are outside the watched
+    /// directory or no longer on disk. With `dry_run`, only reports what
+    /// would be removed.
+    pub async fn collect_garbage(&self, dry_run: bool) -> Result<gc::GcReport> {
+        gc::collect_garbage(&self.indexer, dry_run).await
+    }
+
+    async fn collect_garbage_periodically(&self) {
+        let hours = self.config().gc.interval_hours;
+        if hours == 0 {
+            return std::future::pending().await;
+        }
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(hours * 3600));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.collect_garbage(false).await {
+                warn!("Garbage collection failed: {}", e);
+            }
+        }
+    }
+
+    /// This node's id and the latest entry in its change log, for peers that
+    /// mirror it.
+    pub async fn local_checkpoint(&self) -> Result<sync::Checkpoint> {
+        let node_id = self.node_id().to_string();
+        Ok(self
+            .indexer
+            .db
+            .call(move |db| {
+                let last_seq = db.change_log()?.last_seq()?;
+                Ok(sync::Checkpoint { node_id, last_seq })
+            })
+            .await?)
+    }
+
+    /// The batch covering up to `limit` of this node's change log entries
+    /// after `after`. It has no changes once the caller is up to date.
+    pub async fn changes_since(&self, after: i64, limit: usize) -> Result<sync::SyncBatch> {
+        let node_id = self.node_id().to_string();
+        let limit = limit.clamp(1, sync::MAX_PULL_BATCH);
+        let batch = self
+            .indexer
+            .db
+            .call(move |db| db.snapshot(|tx| sync::collect_batch(tx, &node_id, after, limit)))
+            .await?;
+        Ok(batch.unwrap_or_else(|| sync::SyncBatch {
+            version: sync::SYNC_FORMAT_VERSION,
+            node_id: self.node_id().to_string(),
+            last_seq: after,
+            changes: Vec::new(),
+        }))
+    }
+
+    /// Where this database, acting as an aggregator, is in `node_id`'s
+    /// change log.
+    pub async fn checkpoint(&self, node_id: &str) -> Result<sync::Checkpoint> {
+        let node_id = node_id.to_string();
+        Ok(self
+            .indexer
+            .db
+            .call(move |db| {
+                let last_seq = db.nodes()?.checkpoint(&node_id)?;
+                Ok(sync::Checkpoint { node_id, last_seq })
+            })
+            .await?)
+    }
+
+    /// Drops everything this database, acting as an aggregator, holds for
+    /// `node_id`, for a node that restarts its push from the beginning.
+    pub async fn forget_node(&self, node_id: &str) -> Result<sync::Checkpoint> {
+        let id = node_id.to_string();
+        self.indexer
+            .db
+            .call(move |db| db.nodes()?.delete_node(&id))
+            .await?;
+        info!("Dropped the files of node {} for a full resend", node_id);
+        Ok(sync::Checkpoint {
+            node_id: node_id.to_string(),
+            last_seq: 0,
+        })
+    }
+
+    /// Applies a batch pushed by another node and returns the node's new
+    /// checkpoint.
+    pub async fn receive_batch(&self, batch: sync::SyncBatch) -> Result<sync::Checkpoint> {
+        if batch.version != sync::SYNC_FORMAT_VERSION {
+            return Err(BakoError::InvalidInput(format!(
+                "unsupported sync format version {} (expected {})",
+                batch.version,
+                sync::SYNC_FORMAT_VERSION
+            )));
+        }
+        if batch.node_id == self.node_id() {
+            return Err(BakoError::InvalidInput(
+                "a node can't push to itself".to_string(),
+            ));
+        }
+        let files = batch.changes.len();
+        let node_id = batch.node_id.clone();
+        let last_seq = self
+            .indexer
+            .db
+            .call(move |db| db.transaction(|tx| sync::apply_batch(tx, &batch)))
+            .await?;
+        info!(
+            "Applied {} file changes from node {} (up to change {})",
+            files, node_id, last_seq
+        );
+        Ok(sync::Checkpoint { node_id, last_seq })
+    }
+
+    /// Writes the local index (files, stored text, chunks and vectors) to a
+    /// portable archive at `dest`; see [`archive`] for the format.
+    pub async fn export(&self, dest: impl AsRef<Path>) -> Result<archive::ExportReport> {
+        let db = self.indexer.db.clone();
+        let dest = dest.as_ref().to_path_buf();
+        blocking(move || archive::export(&db, &dest)).await
+    }
+
+    /// Merges an archive written by [`Bako::export`] into this index,
+    /// resolving paths indexed here with different content by `on_conflict`.
+    pub async fn import(
+        &self,
+        src: impl AsRef<Path>,
+        on_conflict: archive::ConflictPolicy,
+    ) -> Result<archive::ImportReport> {
+        let db = self.indexer.db.clone();
+        let src = src.as_ref().to_path_buf();
+        blocking(move || archive::import(&db, &src, on_conflict)).await
+    }
+
+    /// Copies the database to `dest` (a file, or a directory to put a
+    /// timestamped copy in) while bako keeps running.
+    pub async fn backup(&self, dest: impl AsRef<Path>) -> Result<maintenance::BackupReport> {
+        let dest = std::path::absolute(dest.as_ref()).map_err(BakoError::io(dest.as_ref()))?;
+        let live = std::path::absolute(&self.config().db_path)
+            .map_err(BakoError::io(&self.config().db_path))?;
+        if dest == live {
+            return Err(BakoError::InvalidInput(
+                "the backup can't overwrite the database itself".to_string(),
+            ));
+        }
+        let db = self.indexer.db.clone();
+        blocking(move || maintenance::backup(&db, &dest)).await
+    }
+
+    /// Rebuilds the database file to give back space left by deleted rows.
+    pub async fn vacuum(&self) -> Result<maintenance::VacuumReport> {
+        let db = self.indexer.db.clone();
+        blocking(move || maintenance::vacuum(&db)).await
+    }
+
+    /// Checks the database file and looks for orphaned rows, deleting them
+    /// with `repair`.
+    pub async fn check(&self, repair: bool) -> Result<maintenance::CheckReport> {
+        let db = self.indexer.db.clone();
+        blocking(move || maintenance::check(&db, repair)).await
+    }
+
+    /// Indexing metrics in the Prometheus text format, with the queue depth
+    /// and database size read now.
+    pub async fn metrics(&self) -> Result<String> {
+        let (queue, size) = self
+            .indexer
+            .db
+            .call(|db| {
+                let queue = db.jobs()?.get_queue_size()?;
+                Ok((queue, db.size()?))
+            })
+            .await?;
+        let metrics = &self.indexer.metrics;
+        metrics.set_queue_depth(queue);
+        metrics.set_database_size(size.bytes, size.free_bytes);
+        Ok(metrics.encode())
+    }
+
+    /// Index health and progress, as shown by `bako status`.
+    pub async fn status(&self) -> Result<status::StatusReport> {
+        let config = Arc::clone(&self.indexer.config);
+        Ok(self
+            .indexer
+            .db
+            .call(move |db| status::collect(db, &config))
+            .await?)
+    }
+
+    /// Token usage totals grouped by `by`, from `since` (YYYY-MM-DD) onwards.
+    pub async fn usage(
+        &self,
+        by: UsageGrouping,
+        since: Option<&str>,
+    ) -> Result<Vec<UsageSummary>> {
+        let since = since.map(str::to_string);
+        Ok(self
+            .indexer
+            .db
+            .call(move |db| db.usage()?.summarize(by, since.as_deref()))
+            .await?)
+    }
+
+    /// Queues every file for re-embedding with the configured model. The
+    /// current vectors stay searchable until all of them are done.
+    pub async fn reembed(&self) -> Result<reembed::ReembedStart> {
+        let configured = embeddings::configured_model(self.config())?;
+        Ok(self
+            .indexer
+            .db
+            .call(move |db| db.transaction(|tx| reembed::start(tx, configured)))
+            .await?)
+    }
+
+    /// The embedder, once the provider is built and reachable. The lock is
+    /// released while the provider is built and probed, and before the
+    /// caller uses it, so searches don't wait on queue runs or slow probes.
+    /// An embedder for `model`: the configured provider when it embeds with
+    /// that model (or no model is given), otherwise one built for it.
+    async fn embedder_for(
+        &self,
+        model: Option<embeddings::EmbeddingModel>,
+    ) -> Result<Arc<embeddings::Embedder>> {
+        let embedder = self.ready_embedder().await?;
+        match model {
+            Some(model) if model != embedder.model_info() => Ok(Arc::new(
+                embeddings::Embedder::for_model(self.config(), &model).await?,
+            )),
+            _ => Ok(embedder),
+        }
+    }
+
+    async fn ready_embedder(&self) -> Result<Arc<embeddings::Embedder>> {
+        let embedder = {
+            let provider = self.provider.lock().await;
+            if let Some(embedder) = provider.ready() {
+                return Ok(embedder);
+            }
+            provider.embedder()
+        };
+        let probe = provider::probe(embedder, &self.indexer.db, self.config()).await;
+        let mut provider = self.provider.lock().await;
+        if provider.update(&self.indexer.db, probe).await
+            && let Some(embedder) = provider.embedder()
+        {
+            return Ok(embedder);
+        }
+        let reason = match provider.health() {
+            Some(ProviderHealth::Unavailable(reason)) => reason.clone(),
+            _ => format!("{} provider not initialized", self.config().embedding_provider),
+        };
+        Err(BakoError::ProviderUnavailable(reason))
+    }
+}
+
+/// Runs file and database work that doesn't fit [`Database::call`] on the
+/// blocking thread pool.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(BakoError::Io {
+            path: std::path::PathBuf::new(),
+            source: std::io::Error::new(std::io::ErrorKind::Interrupted, e),
+        }),
+    }
+}
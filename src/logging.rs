@@ -1,22 +1,87 @@
+use std::io::IsTerminal;
+
 use tracing::{subscriber::set_global_default};
-use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{self, Rotation};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{EnvFilter, Layer, Registry, fmt, prelude::*};
+
+use crate::config::{LogDestination, LogFormat, LogRotation, LoggingConfig};
+use crate::error::{BakoError, Result};
+
+/// Keeps file logging alive: lines still buffered are written out when it is
+/// dropped, so hold it until the program exits.
+#[must_use = "dropping the guard stops file logging"]
+pub struct LogGuard {
+    _worker: Option<WorkerGuard>,
+}
+
+/// Sets up logging as `config` describes. `RUST_LOG`, when set, takes
+/// precedence over `config.level`.
+pub fn init(config: &LoggingConfig) -> Result<LogGuard> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.level).map_err(|e| {
+            BakoError::Config(format!("Invalid [logging] level {:?}: {}", config.level, e))
+        })?,
+    };
+
+    let (writer, worker, ansi) = match config.destination {
+        // Colours only on a terminal, so redirected and journald output stays
+        // free of escape codes.
+        LogDestination::Stdout => (
+            BoxMakeWriter::new(std::io::stdout),
+            None,
+            std::io::stdout().is_terminal(),
+        ),
+        LogDestination::Stderr => (
+            BoxMakeWriter::new(std::io::stderr),
+            None,
+            std::io::stderr().is_terminal(),
+        ),
+        LogDestination::File => {
+            let dir = config.log_directory()?;
+            std::fs::create_dir_all(&dir).map_err(BakoError::io(&dir))?;
+            let rotation = match config.rotation {
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            };
+            let appender = rolling::Builder::new()
+                .rotation(rotation)
+                .filename_prefix("bako")
+                .filename_suffix("log")
+                .max_log_files(config.max_files.max(1))
+                .build(&dir)
+                .map_err(|e| {
+                    BakoError::Config(format!(
+                        "Failed to open log files in {}: {}",
+                        dir.display(),
+                        e
+                    ))
+                })?;
+            let (writer, worker) = tracing_appender::non_blocking(appender);
+            (BoxMakeWriter::new(writer), Some(worker), false)
+        }
+    };
 
-pub fn init() -> Result<(), Box<dyn std::error::Error>> {
-    let fmt_layer = fmt::layer()
-        .pretty()
+    let layer = fmt::layer()
         .with_target(false)
         .with_level(true)
-        .with_ansi(true)
         .with_thread_ids(false)
         .with_thread_names(false)
         .with_file(false)
         .with_line_number(false)
-        .with_writer(std::io::stdout);
+        .with_writer(writer);
+    let fmt_layer: Box<dyn Layer<Registry> + Send + Sync> = match config.format {
+        LogFormat::Pretty => layer.pretty().with_ansi(ansi).boxed(),
+        LogFormat::Compact => layer.compact().with_ansi(ansi).boxed(),
+        LogFormat::Json => layer.json().with_ansi(false).boxed(),
+    };
 
-    let subscriber = tracing_subscriber::registry()
-        .with(EnvFilter::from_default_env())
-        .with(fmt_layer);
+    let subscriber = tracing_subscriber::registry().with(fmt_layer).with(filter);
 
-    set_global_default(subscriber)?;
-    Ok(())
+    set_global_default(subscriber)
+        .map_err(|e| BakoError::Config(format!("Failed to set up logging: {}", e)))?;
+    Ok(LogGuard { _worker: worker })
 }
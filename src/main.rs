@@ -1,218 +1,327 @@
-use serde_json;
-use std::path::Path;
-use tokio::fs;
-use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-mod db;
-use db::Database;
-use db::job_repo::Job;
-mod config;
-mod embeddings;
-mod file;
-mod logging;
-mod watcher;
-mod utils;
+use bako::progress::Progress;
+use bako::reembed::ReembedStart;
+use bako::{Bako, BakoError, BakoEvent, Config, Result, http, logging, status, usage};
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use tokio::sync::broadcast;
+use tracing::info;
 
-async fn handle_file_event(
-    event: db::FileEvent,
-    db: &Database,
-) -> Result<(), Box<dyn std::error::Error>> {
-    info!(
-        "File event received: {} for {}",
-        event.event_type, event.path
-    );
+mod cli;
 
-    match event.event_type {
-        db::FileEventType::Create => {
-            if let Err(e) = process_create_event(&event, db).await {
-                error!("Failed to process create event for {}: {}", event.path, e);
-                return Err(e);
-            }
-        }
-        db::FileEventType::Modify => {
-            if let Err(e) = process_modify_event(&event, db).await {
-                error!("Failed to process modify event for {}: {}", event.path, e);
-                return Err(e);
-            }
-        }
-        db::FileEventType::Delete => {
-            if let Err(e) = process_delete_event(&event, db).await {
-                error!("Failed to process delete event for {}: {}", event.path, e);
-                return Err(e);
-            }
-        }
+/// For `--now`: embeds what is queued instead of waiting for the running bako.
+async fn embed_now(bako: &Bako) -> Result<()> {
+    if bako.process_queue().await? {
+        println!("Embedded the queued files.");
+    } else {
+        println!("The embeddings provider is unavailable; the files stay queued.");
     }
-
     Ok(())
 }
 
-async fn process_create_event(
-    event: &db::FileEvent,
-    db: &Database,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file_type = utils::get_file_type(&event.path)?;
-    let hash = utils::hash_file(&event.path).await?;
-    let size = std::fs::metadata(&event.path)?.len() as i64;
+/// Progress is logged at most this often when there is no terminal to draw on.
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+fn progress_summary(progress: &Progress) -> String {
+    let counts = &progress.counts;
+    let mut summary = format!(
+        "{} embedded, {} skipped, {} failed",
+        counts.embedded, counts.skipped, counts.failed
+    );
+    if let Some(secs) = progress.eta_secs {
+        summary.push_str(&format!(
+            ", about {} left",
+            status::format_duration(Duration::from_secs(secs))
+        ));
+    }
+    summary
+}
 
-    match db.files().upsert_file(&event.path, &file_type, &hash, size) {
-        Ok(file) => {
+/// Shows indexing progress while `bako` or `bako serve` runs: as a progress
+/// bar when stderr is a terminal, otherwise as periodic log lines.
+async fn show_progress(mut events: broadcast::Receiver<BakoEvent>) {
+    let terminal = std::io::stderr().is_terminal();
+    let mut bar: Option<ProgressBar> = None;
+    let mut last_logged: Option<Instant> = None;
+    loop {
+        let progress = match events.recv().await {
+            Ok(BakoEvent::IndexingProgress(progress)) => progress,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let counts = &progress.counts;
+        if terminal {
+            let bar = bar.get_or_insert_with(|| {
+                ProgressBar::new(0).with_style(
+                    ProgressStyle::with_template("{bar:30} {pos}/{len} files  {msg}")
+                        .expect("progress template is valid"),
+                )
+            });
+            bar.set_length(counts.discovered as u64);
+            bar.set_position(progress.finished() as u64);
+            bar.set_message(progress_summary(&progress));
+        } else if progress.is_done()
+            || last_logged.is_none_or(|at| at.elapsed() >= PROGRESS_LOG_INTERVAL)
+        {
             info!(
-                "Successfully inserted file: {} (ID: {})",
-                file.path, file.id
+                target: bako::progress::LOG_TARGET,
+                discovered = counts.discovered,
+                hashed = counts.hashed,
+                extracted = counts.extracted,
+                embedded = counts.embedded,
+                skipped = counts.skipped,
+                failed = counts.failed,
+                pending = counts.pending,
+                eta_secs = progress.eta_secs,
+                "Indexing progress: {}/{} files ({})",
+                progress.finished(),
+                counts.discovered,
+                progress_summary(&progress)
             );
-            db.jobs().insert_job(&file.id)?;
+            last_logged = Some(Instant::now());
         }
-        Err(e) => {
-            error!("Failed to insert file {}: {}", event.path, e);
-            return Err(Box::new(e));
+        if progress.is_done() {
+            if let Some(bar) = bar.take() {
+                bar.finish();
+            }
+            last_logged = None;
         }
     }
-
-    Ok(())
-}
-
-async fn process_delete_event(
-    event: &db::FileEvent,
-    db: &Database,
-) -> Result<(), Box<dyn std::error::Error>> {
-    db.files().delete_file(&event.path)?;
-    Ok(())
-}
-
-async fn process_modify_event(
-    event: &db::FileEvent,
-    db: &Database,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file_type = utils::get_file_type(&event.path)?;
-    let hash = utils::hash_file(&event.path).await?;
-    let size = std::fs::metadata(&event.path)?.len() as i64;
-    let file = db.files().upsert_file(&event.path, &file_type, &hash, size)?;
-    if db.jobs().get_jobs_by_file_id(&file.id, "pending").map_or(true, |jobs| jobs.is_empty()) {
-        db.jobs().insert_job(&file.id)?;
-    }
-    Ok(())
 }
 
-async fn init_app() -> Result<(Database, embeddings::Embedder, config::Config), Box<dyn std::error::Error>> {
-    logging::init()?;
-    let config = config::Config::load_or_init().await?;
+async fn run_command(cli: cli::Cli) -> Result<ExitCode> {
+    let config = match &cli.config {
+        Some(path) => Config::load(path).await?,
+        None => Config::load_or_init().await?,
+    };
+    let _log_guard = logging::init(&config.logging)?;
     info!("Configuration loaded: {:?}", config);
+    let bako = Bako::open(config).await?;
 
-    let db_path = Path::new(&config.db_path);
-    info!("Initializing database at {}", db_path.display());
-    let db = Database::new(db_path)?;
-
-    info!("Initializing OpenAI embeddings client");
-    let embedder = match embeddings::Embedder::new().await {
-        Ok(embedder) => {
-            info!("Embeddings client initialized successfully");
-            embedder
+    match cli.command {
+        None => {
+            tokio::spawn(show_progress(bako.subscribe()));
+            match bako.config().metrics_listen.clone() {
+                Some(listen) => {
+                    let listener = http::bind(&listen, &bako.config().http_allowed_hosts).await?;
+                    let bako = Arc::new(bako);
+                    tokio::try_join!(bako.run(), http::serve_metrics(Arc::clone(&bako), listener))?;
+                }
+                None => bako.run().await?,
+            }
+            info!("Exiting application");
         }
-        Err(e) => {
-            warn!(
-                "Failed to initialize embeddings client: {}. Running without embeddings support.",
-                e
+        Some(cli::Command::Usage { by, since }) => {
+            let rows = bako.usage(by, since.as_deref()).await?;
+            usage::print_report(by, &rows, &bako.config().token_prices);
+        }
+        Some(cli::Command::Reembed) => match bako.reembed().await? {
+            ReembedStart::UpToDate(model) => {
+                println!("Embeddings already use {}; nothing to re-embed.", model);
+            }
+            ReembedStart::Queued {
+                from,
+                target,
+                queued,
+                open,
+            } => {
+                println!(
+                    "Re-embedding from {} to {}: queued {} files ({} still to do).",
+                    from, target, queued, open
+                );
+                println!(
+                    "bako processes them in the background; search keeps using the old vectors until all are done."
+                );
+            }
+        },
+        Some(cli::Command::Index { path, now }) => {
+            let report = bako.index_path(&path).await?;
+            println!("Queued {} files for embedding.", report.files.len());
+            for failure in &report.failed {
+                println!("  failed: {}: {}", failure.path, failure.error);
+            }
+            if now {
+                embed_now(&bako).await?;
+            }
+        }
+        Some(cli::Command::IndexText {
+            path,
+            metadata,
+            text,
+            now,
+        }) => {
+            let text = match text {
+                Some(text) => text,
+                None => std::io::read_to_string(std::io::stdin()).map_err(BakoError::io("stdin"))?,
+            };
+            let metadata = (!metadata.is_empty()).then(|| {
+                metadata
+                    .into_iter()
+                    .map(|(key, value)| (key, serde_json::Value::String(value)))
+                    .collect::<serde_json::Map<_, _>>()
+                    .into()
+            });
+            let file = bako.index_text(&path, text, metadata).await?;
+            println!("Queued {} ({} bytes) for embedding.", file.path, file.size);
+            if now {
+                embed_now(&bako).await?;
+            }
+        }
+        Some(cli::Command::Serve { listen, aggregator }) => {
+            let listen = listen.unwrap_or_else(|| bako.config().http_listen.clone());
+            let listener = http::bind(&listen, &bako.config().http_allowed_hosts).await?;
+            let bako = Arc::new(bako);
+            tokio::spawn(show_progress(bako.subscribe()));
+            tokio::try_join!(
+                bako.run(),
+                http::serve(Arc::clone(&bako), listener, aggregator)
+            )?;
+        }
+        Some(cli::Command::Pull) => {
+            let report = bako.pull_changes().await?;
+            println!(
+                "Pulled {} file changes in {} batches from node {}; mirrored up to change {}.",
+                report.files, report.batches, report.node_id, report.last_seq
             );
-            return Err(e);
         }
-    };
-    Ok((db, embedder, config))
-}
-
-async fn process_event_queue(
-    db: &Database,
-    embedder: &embeddings::Embedder,
-    batch_size: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let queue_size = db.jobs().get_queue_size()?;
-    info!(
-        "Processing event queue (queue size: {}, batch size: {})",
-        queue_size, batch_size
-    );
-
-    let jobs = db.jobs().get_jobs("pending")?;
-    let mut completed_jobs: Vec<Job> = vec![];
-    for job in jobs {
-        info!("Processing job: {}", job.id);
-        let file = db.files().get_file(&job.file_id)?;
-        let content = fs::read_to_string(&file.path).await?;
-        let embeddings_response = embedder.genereate_embeddings(&content).await?;
-        let embedding_vector = &embeddings_response.data[0].embedding;
-        let embedding_json = serde_json::to_string(embedding_vector)
-            .map_err(|e| format!("Failed to serialize embedding to JSON: {}", e))?;
-        db.embeddings().insert_embedding(&job.file_id, &embedding_json)?;
-        completed_jobs.push(job);
-    }
-
-    if !completed_jobs.is_empty() {
-        db.jobs().update_job_batch(
-            completed_jobs.iter().map(|job| job.id.clone()).collect(),
-            "completed",
-            None,
-        )?;
-    }
-
-    Ok(())
-}
-
-async fn run_main_event_loop(
-    mut fs_event_receiver: mpsc::Receiver<db::FileEvent>,
-    db: &Database,
-    embedder: &embeddings::Embedder,
-    process_interval: std::time::Duration,
-    batch_size: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Starting main event loop");
-    let mut interval = tokio::time::interval(process_interval);
-    loop {
-        tokio::select! {
-            Some(event) = fs_event_receiver.recv() => {
-                debug!("Received file system event: {} for {}", event.event_type, event.path);
-                if let Err(e) = handle_file_event(event, db).await {
-                    error!("Error handling event: {:?}", e);
-                }
+        Some(cli::Command::Push) => {
+            let report = bako.push_changes().await?;
+            println!(
+                "Pushed {} file changes in {} batches; the aggregator is at change {}.",
+                report.files, report.batches, report.last_seq
+            );
+        }
+        Some(cli::Command::Export { dest }) => {
+            let report = bako.export(&dest).await?;
+            println!(
+                "Exported {} files, {} chunks and {} vectors to {}.",
+                report.files,
+                report.chunks,
+                report.vectors,
+                dest.display()
+            );
+        }
+        Some(cli::Command::Import { src, on_conflict }) => {
+            let report = bako.import(&src, on_conflict).await?;
+            println!(
+                "Imported {}: {} added, {} replaced, {} unchanged, {} kept as they were; {} vectors.",
+                src.display(),
+                report.added,
+                report.replaced,
+                report.unchanged,
+                report.kept,
+                report.vectors
+            );
+            if report.queued > 0 {
+                println!(
+                    "{} files have no vectors from this index's model and were queued for embedding.",
+                    report.queued
+                );
             }
-
-            _ = interval.tick() => {
-                if let Err(e) = process_event_queue(db, embedder, batch_size).await {
-                    error!("Error processing event queue: {}", e);
+        }
+        Some(cli::Command::Db { command }) => match command {
+            cli::DbCommand::Backup { dest } => {
+                let report = bako.backup(&dest).await?;
+                println!(
+                    "Backed up the database to {} ({}).",
+                    report.path.display(),
+                    status::format_bytes(report.bytes)
+                );
+            }
+            cli::DbCommand::Vacuum => {
+                let report = bako.vacuum().await?;
+                println!(
+                    "Vacuumed the database: {} -> {}.",
+                    status::format_bytes(report.before.bytes),
+                    status::format_bytes(report.after.bytes)
+                );
+            }
+            cli::DbCommand::Check { repair } => {
+                let report = bako.check(repair).await?;
+                for problem in &report.problems {
+                    println!("integrity: {}", problem);
+                }
+                for orphans in &report.orphans {
+                    println!(
+                        "orphans: {} {} rows without their {} row",
+                        orphans.count, orphans.table, orphans.parent
+                    );
+                }
+                if report.repaired > 0 {
+                    println!("Deleted {} orphaned rows.", report.repaired);
+                } else if !report.orphans.is_empty() {
+                    println!("Run `bako db check --repair` to delete the orphaned rows.");
+                }
+                if !report.problems.is_empty() {
+                    println!("The database file is damaged; restore it from a backup.");
+                }
+                if !report.is_ok() {
+                    return Ok(ExitCode::FAILURE);
+                }
+                if report.repaired == 0 {
+                    println!("No problems found.");
                 }
             }
-
-            else => {
-                info!("All channels closed, exiting main loop");
-                break;
+        },
+        Some(cli::Command::Gc { dry_run }) => {
+            let report = bako.collect_garbage(dry_run).await?;
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            println!(
+                "{} {} completed jobs older than {} days.",
+                verb,
+                report.jobs,
+                bako.config().gc.completed_job_retention_days
+            );
+            println!(
+                "{} {} files outside the watched directory and {} deleted files.",
+                verb,
+                report.outside_roots.len(),
+                report.missing.len()
+            );
+            for path in &report.outside_roots {
+                println!("  outside: {}", path);
+            }
+            for path in &report.missing {
+                println!("  deleted: {}", path);
+            }
+            println!("{} {} superseded change log entries.", verb, report.change_log);
+        }
+        Some(cli::Command::Status) => status::print(&bako.status().await?),
+        Some(cli::Command::Search { query, limit }) => {
+            let hits = bako.search(&query, limit).await?;
+            if hits.is_empty() {
+                println!("No matches.");
+            }
+            for hit in hits {
+                match &hit.node_id {
+                    Some(node_id) => println!("{:.3}  {} (node {})", hit.score, hit.path, node_id),
+                    None => println!("{:.3}  {}", hit.score, hit.path),
+                }
+                if let Some(content) = hit.content {
+                    let excerpt: String = content.chars().take(200).collect();
+                    println!("       {}", excerpt.replace('\n', " "));
+                }
             }
         }
     }
-
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (db, embedder, config) = init_app().await?;
-    let target_dir = Path::new(&config.watch_directory);
-    let fs_event_receiver = watcher::setup_file_watcher(target_dir, config.watcher_poll_duration_secs)?;
-    let process_interval = std::time::Duration::from_secs(config.queue_process_interval_secs);
-    let batch_size = config.queue_batch_size;
-
-    info!(
-        "Starting queue-based event processing (interval: {:?}, batch size: {})",
-        process_interval, batch_size
-    );
-
-    run_main_event_loop(
-        fs_event_receiver,
-        &db, 
-        &embedder,
-        process_interval,
-        batch_size,
-    )
-    .await?;
-
-    info!("Exiting application");
-
-    Ok(())
+async fn main() -> ExitCode {
+    let cli = cli::Cli::parse();
+    match run_command(cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            if let Some(hint) = e.hint() {
+                eprintln!("hint: {}", hint);
+            }
+            ExitCode::FAILURE
+        }
+    }
 }
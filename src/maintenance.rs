@@ -0,0 +1,217 @@
+//! Keeping the database file healthy: online backups (on request and on a
+//! schedule), vacuuming and integrity checks.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::BackupConfig;
+use crate::db::{Database, DatabaseSize};
+use crate::db::integrity_repo::Orphans;
+use crate::error::{BakoError, Result};
+
+/// Scheduled backups are named `bako-<timestamp>.db`; only files named like
+/// this are ever pruned.
+const BACKUP_PREFIX: &str = "bako-";
+const BACKUP_SUFFIX: &str = ".db";
+
+/// What [`backup`] wrote.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupReport {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// The database's size before and after [`vacuum`].
+#[derive(Debug, Clone, Copy)]
+pub struct VacuumReport {
+    pub before: DatabaseSize,
+    pub after: DatabaseSize,
+}
+
+/// What [`check`] found.
+#[derive(Debug, Clone)]
+pub struct CheckReport {
+    /// Problems reported by `PRAGMA integrity_check`.
+    pub problems: Vec<String>,
+    pub orphans: Vec<Orphans>,
+    /// Orphaned rows deleted, when asked to repair.
+    pub repaired: usize,
+}
+
+impl CheckReport {
+    /// Whether the database is sound now, counting repaired orphans as fixed.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty() && (self.orphans.is_empty() || self.repaired > 0)
+    }
+}
+
+/// `bako-<timestamp>.db` for the database's current time, as
+/// `YYYYMMDD-HHMMSS` so names sort chronologically.
+fn backup_name(db: &Database) -> rusqlite::Result<String> {
+    let stamp: String = db
+        .now()?
+        .chars()
+        .filter_map(|c| match c {
+            '-' | ':' => None,
+            ' ' => Some('-'),
+            c => Some(c),
+        })
+        .collect();
+    Ok(format!("{}{}{}", BACKUP_PREFIX, stamp, BACKUP_SUFFIX))
+}
+
+/// Copies the live database to `dest`, or into it as `bako-<timestamp>.db`
+/// when it is a directory. The copy is written next to its destination and
+/// renamed into place, so an interrupted backup never leaves a torn file.
+pub fn backup(db: &Database, dest: &Path) -> Result<BackupReport> {
+    let dest = if dest.is_dir() {
+        dest.join(backup_name(db)?)
+    } else {
+        dest.to_path_buf()
+    };
+    let partial = dest.with_extension("partial");
+    let _ = std::fs::remove_file(&partial);
+    if let Err(e) = db.backup_to(&partial) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e.into());
+    }
+    std::fs::rename(&partial, &dest).map_err(BakoError::io(&dest))?;
+    let bytes = std::fs::metadata(&dest)
+        .map_err(BakoError::io(&dest))?
+        .len();
+    info!("Backed up the database to {} ({} bytes)", dest.display(), bytes);
+    Ok(BackupReport { path: dest, bytes })
+}
+
+/// Scheduled backups in `dir`, oldest first.
+fn scheduled_backups(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX))
+        })
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// How long until the next scheduled backup is due: now if `config`'s
+/// directory holds none yet.
+pub fn next_backup_in(config: &BackupConfig) -> Duration {
+    let every = Duration::from_secs(config.interval_hours.max(1) * 3600);
+    let Some(dir) = &config.directory else {
+        return every;
+    };
+    let newest = scheduled_backups(Path::new(dir))
+        .ok()
+        .and_then(|backups| backups.last().cloned())
+        .and_then(|path| std::fs::metadata(path).ok())
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|modified| modified.elapsed().ok());
+    match newest {
+        Some(age) => every.saturating_sub(age),
+        None => Duration::ZERO,
+    }
+}
+
+/// Takes a backup into `config.directory` and deletes the oldest scheduled
+/// backups beyond `config.keep`.
+pub fn scheduled_backup(db: &Database, config: &BackupConfig) -> Result<BackupReport> {
+    let dir = config
+        .directory
+        .as_deref()
+        .ok_or_else(|| BakoError::Config("[backup] directory is not set".to_string()))?;
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir).map_err(BakoError::io(dir))?;
+    let report = backup(db, &dir.join(backup_name(db)?))?;
+
+    let backups = scheduled_backups(dir).map_err(BakoError::io(dir))?;
+    let excess = backups.len().saturating_sub(config.keep.max(1));
+    for old in &backups[..excess] {
+        match std::fs::remove_file(old) {
+            Ok(()) => info!("Deleted old backup {}", old.display()),
+            Err(e) => warn!("Failed to delete old backup {}: {}", old.display(), e),
+        }
+    }
+    Ok(report)
+}
+
+/// Rebuilds the database file to give back the space left by deleted rows.
+pub fn vacuum(db: &Database) -> Result<VacuumReport> {
+    let before = db.size()?;
+    db.vacuum()?;
+    let after = db.size()?;
+    info!(
+        "Vacuumed the database from {} to {} bytes",
+        before.bytes, after.bytes
+    );
+    Ok(VacuumReport { before, after })
+}
+
+/// Runs SQLite's integrity check and looks for rows whose file or chunk is
+/// gone. With `repair`, those rows are deleted; damage found by the
+/// integrity check can only be fixed by restoring a backup.
+pub fn check(db: &Database, repair: bool) -> Result<CheckReport> {
+    let problems = db.integrity()?.integrity_check()?;
+    let orphans = db.integrity()?.orphans()?;
+    let repaired = if repair && !orphans.is_empty() {
+        let deleted = db.transaction(|tx| tx.integrity().delete_orphans())?;
+        info!("Deleted {} orphaned rows", deleted);
+        deleted
+    } else {
+        0
+    };
+    Ok(CheckReport {
+        problems,
+        orphans,
+        repaired,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::temp_db;
+    use crate::file::FileSource;
+
+    #[test]
+    fn scheduled_backups_are_readable_and_pruned() {
+        let db = temp_db();
+        db.files().unwrap()
+            .upsert_file("/notes/a.md", "text/plain", "h", 1, FileSource::Watched, None)
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("bako-backups-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["bako-20250101-000000.db", "bako-20250102-000000.db", "notes.txt"] {
+            std::fs::write(dir.join(name), "old").unwrap();
+        }
+        let config = BackupConfig {
+            directory: Some(dir.display().to_string()),
+            interval_hours: 24,
+            keep: 2,
+        };
+
+        let report = scheduled_backup(&db, &config).unwrap();
+        assert!(next_backup_in(&config) > Duration::from_secs(23 * 3600));
+        let copy = Database::new(&report.path).unwrap();
+        assert!(copy.files().unwrap().find_by_path("/notes/a.md").unwrap().is_some());
+        drop(copy);
+
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| !name.ends_with("-wal") && !name.ends_with("-shm"))
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], "bako-20250102-000000.db");
+        assert_eq!(names[2], "notes.txt");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Counters and gauges about indexing, served on `/metrics` in the
+//! Prometheus text format.
+
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{Histogram, exponential_buckets};
+use prometheus_client::registry::Registry;
+
+use crate::db::FileEventType;
+use crate::embeddings::RequestUsage;
+use crate::events::BakoEvent;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EventLabels {
+    event: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct JobLabels {
+    status: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ProviderLabels {
+    provider: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ModelLabels {
+    provider: &'static str,
+    model: String,
+}
+
+fn latency_histogram() -> Histogram {
+    // 10ms to about 40s.
+    Histogram::new(exponential_buckets(0.01, 2.0, 13))
+}
+
+/// One bako's metrics. Counters are updated as things happen; the queue
+/// and database gauges are refreshed by [`crate::Bako::metrics`] on every
+/// scrape.
+pub struct Metrics {
+    registry: Registry,
+    file_events: Family<EventLabels, Counter>,
+    watcher_errors: Counter,
+    jobs: Family<JobLabels, Counter>,
+    embedding_requests: Family<ProviderLabels, Histogram, fn() -> Histogram>,
+    embedding_tokens: Family<ModelLabels, Counter>,
+    queue_depth: Gauge,
+    database_size: Gauge,
+    database_free: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut metrics = Metrics {
+            registry: Registry::with_prefix("bako"),
+            file_events: Family::default(),
+            watcher_errors: Counter::default(),
+            jobs: Family::default(),
+            embedding_requests: Family::new_with_constructor(latency_histogram),
+            embedding_tokens: Family::default(),
+            queue_depth: Gauge::default(),
+            database_size: Gauge::default(),
+            database_free: Gauge::default(),
+        };
+        let registry = &mut metrics.registry;
+        registry.register(
+            "file_events",
+            "File system events received from the watcher, by type",
+            metrics.file_events.clone(),
+        );
+        registry.register(
+            "watcher_errors",
+            "Errors reported by the file watcher",
+            metrics.watcher_errors.clone(),
+        );
+        registry.register(
+            "jobs",
+            "Jobs finished, by outcome",
+            metrics.jobs.clone(),
+        );
+        registry.register(
+            "embedding_request_duration_seconds",
+            "Time taken by successful embeddings requests, including retries",
+            metrics.embedding_requests.clone(),
+        );
+        registry.register(
+            "embedding_tokens",
+            "Tokens billed for embedding file text",
+            metrics.embedding_tokens.clone(),
+        );
+        registry.register(
+            "queue_depth",
+            "Jobs waiting to be embedded",
+            metrics.queue_depth.clone(),
+        );
+        registry.register(
+            "database_size_bytes",
+            "Size of the SQLite database",
+            metrics.database_size.clone(),
+        );
+        registry.register(
+            "database_free_bytes",
+            "Free pages in the SQLite database that `bako db vacuum` would give back",
+            metrics.database_free.clone(),
+        );
+        metrics
+    }
+
+    pub fn file_event(&self, event_type: FileEventType) {
+        let event = match event_type {
+            FileEventType::Create => "create",
+            FileEventType::Modify => "modify",
+            FileEventType::Delete => "delete",
+        };
+        self.file_events.get_or_create(&EventLabels { event }).inc();
+    }
+
+    pub fn watcher_error(&self) {
+        self.watcher_errors.inc();
+    }
+
+    /// Counts the job outcome an event reports, if any.
+    pub fn event(&self, event: &BakoEvent) {
+        let status = match event {
+            BakoEvent::FileEmbedded { .. } => "completed",
+            BakoEvent::FileFailed { .. } => "failed",
+            BakoEvent::FileSkipped { .. } => "skipped",
+            _ => return,
+        };
+        self.jobs.get_or_create(&JobLabels { status }).inc();
+    }
+
+    pub fn embedding_request(&self, provider: &'static str, usage: &RequestUsage) {
+        self.embedding_requests
+            .get_or_create(&ProviderLabels { provider })
+            .observe(usage.elapsed.as_secs_f64());
+        self.embedding_tokens
+            .get_or_create(&ModelLabels {
+                provider,
+                model: usage.model.clone(),
+            })
+            .inc_by(usage.total_tokens as u64);
+    }
+
+    pub fn set_queue_depth(&self, jobs: usize) {
+        self.queue_depth.set(jobs as i64);
+    }
+
+    pub fn set_database_size(&self, bytes: u64, free_bytes: u64) {
+        self.database_size.set(bytes as i64);
+        self.database_free.set(free_bytes as i64);
+    }
+
+    /// Everything in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        prometheus_client::encoding::text::encode(&mut out, &self.registry)
+            .expect("metrics encode to a String");
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_job_outcomes_from_events() {
+        let metrics = Metrics::new();
+        metrics.file_event(FileEventType::Modify);
+        metrics.event(&BakoEvent::FileFailed {
+            file_id: "f".to_string(),
+            path: "/a".to_string(),
+            error: "boom".to_string(),
+        });
+        metrics.event(&BakoEvent::ProviderAvailable);
+        metrics.set_queue_depth(3);
+
+        let text = metrics.encode();
+        assert!(text.contains("bako_file_events_total{event=\"modify\"} 1"));
+        assert!(text.contains("bako_jobs_total{status=\"failed\"} 1"));
+        assert!(!text.contains("status=\"completed\""));
+        assert!(text.contains("bako_queue_depth 3"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Progress through the queue, read from the jobs table and announced as
+//! [`BakoEvent::IndexingProgress`] while a batch of files is being indexed.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::db::Transaction;
+use crate::db::job_repo::RunCounts;
+use crate::events::BakoEvent;
+use crate::indexer::Indexer;
+use crate::status;
+
+/// Target of the progress log lines, which the default log level lets
+/// through so that a bako running as a service still shows progress.
+pub const LOG_TARGET: &str = "bako::progress";
+
+/// How often progress is read while files are being indexed.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How far one indexing run has got. A run starts when files are queued
+/// and ends once none are left pending.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    #[serde(flatten)]
+    pub counts: RunCounts,
+    /// Estimated time until the pending files are done, at the recent rate.
+    pub eta_secs: Option<u64>,
+}
+
+impl Progress {
+    /// Files embedded, skipped or failed so far.
+    pub fn finished(&self) -> usize {
+        self.counts.embedded + self.counts.skipped + self.counts.failed
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.counts.pending == 0
+    }
+}
+
+fn load(tx: &Transaction, since: &str) -> rusqlite::Result<Progress> {
+    let counts = tx.jobs().run_counts(since)?;
+    let eta = match counts.pending {
+        0 => None,
+        pending => tx
+            .jobs()
+            .recent_throughput(status::THROUGHPUT_WINDOW)?
+            .eta(pending),
+    };
+    Ok(Progress {
+        counts,
+        eta_secs: eta.map(|eta| eta.as_secs()),
+    })
+}
+
+/// Announces progress whenever it changes while files are being indexed,
+/// and once more when a run is done. Runs until cancelled.
+pub(crate) async fn report(indexer: &Indexer) {
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    // Returning would end `Bako::run`, so a database that can't be read yet
+    // is retried instead.
+    let mut since = loop {
+        interval.tick().await;
+        match indexer.db.call(|db| db.now()).await {
+            Ok(now) => break now,
+            Err(e) => warn!("Failed to read the time from the database: {}", e),
+        }
+    };
+    let mut last: Option<Progress> = None;
+    loop {
+        interval.tick().await;
+        let run_since = since.clone();
+        let progress = match indexer.db.call(move |db| db.snapshot(|tx| load(tx, &run_since))).await {
+            Ok(progress) => progress,
+            Err(e) => {
+                warn!("Failed to read indexing progress: {}", e);
+                continue;
+            }
+        };
+        if progress.counts.discovered == 0 || last.as_ref() == Some(&progress) {
+            continue;
+        }
+        indexer.emit(BakoEvent::IndexingProgress(progress.clone()));
+        if progress.is_done() {
+            // Whatever is queued next starts a new run.
+            match indexer.db.call(|db| db.now()).await {
+                Ok(now) => since = now,
+                Err(e) => warn!("Failed to read the time from the database: {}", e),
+            }
+            last = None;
+        } else {
+            last = Some(progress);
+        }
+    }
+}
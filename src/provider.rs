@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::db::{Database, Transaction};
+use crate::embeddings::Embedder;
+use crate::events::BakoEvent;
+use crate::reembed;
+
+const STATUS_KEY: &str = "provider_status";
+const DETAIL_KEY: &str = "provider_status_detail";
+const CHECKED_AT_KEY: &str = "provider_checked_at";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderHealth {
+    Available,
+    Unavailable(String),
+}
+
+/// Provider health as last recorded by a running bako.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderStatus {
+    pub status: String,
+    pub detail: Option<String>,
+    pub checked_at: Option<String>,
+}
+
+fn record(tx: &Transaction, health: &ProviderHealth) -> rusqlite::Result<()> {
+    match health {
+        ProviderHealth::Available => {
+            tx.meta().set(STATUS_KEY, "available")?;
+            tx.meta().delete(DETAIL_KEY)?;
+        }
+        ProviderHealth::Unavailable(reason) => {
+            tx.meta().set(STATUS_KEY, "unavailable")?;
+            tx.meta().set(DETAIL_KEY, reason)?;
+        }
+    }
+    tx.meta().set_now(CHECKED_AT_KEY)
+}
+
+pub fn load_status(tx: &Transaction) -> rusqlite::Result<Option<ProviderStatus>> {
+    let Some(status) = tx.meta().get(STATUS_KEY)? else {
+        return Ok(None);
+    };
+    Ok(Some(ProviderStatus {
+        status,
+        detail: tx.meta().get(DETAIL_KEY)?,
+        checked_at: tx.meta().get(CHECKED_AT_KEY)?,
+    }))
+}
+
+/// What building and checking the provider found.
+pub struct Probe {
+    embedder: Option<Arc<Embedder>>,
+    health: ProviderHealth,
+}
+
+/// Builds the provider unless `embedder` already is one, then checks that it
+/// is reachable. Both can take a while, so this runs without holding the
+/// [`ProviderState`] lock; the result is taken in with
+/// [`ProviderState::update`].
+pub async fn probe(embedder: Option<Arc<Embedder>>, db: &Database, config: &Config) -> Probe {
+    let embedder = match embedder {
+        Some(embedder) => embedder,
+        None => match Embedder::new(config).await {
+            Ok(embedder) => {
+                info!("Embeddings provider initialized successfully");
+                let model = embedder.model_info();
+                if let Err(e) = db
+                    .call(move |db| db.transaction(|tx| reembed::check_embedding_config(tx, &model)))
+                    .await
+                {
+                    warn!("Failed to check the embedding model: {}", e);
+                }
+                Arc::new(embedder)
+            }
+            Err(e) => {
+                return Probe {
+                    embedder: None,
+                    health: ProviderHealth::Unavailable(e.to_string()),
+                };
+            }
+        },
+    };
+    let health = match embedder.check_health().await {
+        Ok(()) => ProviderHealth::Available,
+        Err(e) => ProviderHealth::Unavailable(e.to_string()),
+    };
+    Probe {
+        embedder: Some(embedder),
+        health,
+    }
+}
+
+/// The embeddings provider as seen by the queue: it may not have been built
+/// yet (missing key, missing model) or may be unreachable. While it isn't
+/// usable, files are still tracked and their jobs wait in the queue.
+pub struct ProviderState {
+    embedder: Option<Arc<Embedder>>,
+    health: Option<ProviderHealth>,
+    events: broadcast::Sender<BakoEvent>,
+}
+
+impl ProviderState {
+    /// Nothing is built or probed until a [`probe`] is taken in.
+    pub fn new(events: broadcast::Sender<BakoEvent>) -> Self {
+        ProviderState {
+            embedder: None,
+            health: None,
+            events,
+        }
+    }
+
+    pub fn embedder(&self) -> Option<Arc<Embedder>> {
+        self.embedder.clone()
+    }
+
+    /// Health as of the last check; `None` before the first one.
+    pub fn health(&self) -> Option<&ProviderHealth> {
+        self.health.as_ref()
+    }
+
+    /// The embedder, if it is built and was reachable when last checked.
+    pub fn ready(&self) -> Option<Arc<Embedder>> {
+        match self.health {
+            Some(ProviderHealth::Available) => self.embedder.clone(),
+            _ => None,
+        }
+    }
+
+    /// Takes in the result of a [`probe`]. Returns whether the queue can be
+    /// processed.
+    pub async fn update(&mut self, db: &Database, probe: Probe) -> bool {
+        if let Some(embedder) = probe.embedder {
+            self.embedder = Some(embedder);
+        }
+        let available = probe.health == ProviderHealth::Available;
+        self.set_health(db, probe.health).await;
+        available
+    }
+
+    /// Records that the provider failed while processing the queue, so the
+    /// next runs probe it before sending more work.
+    pub async fn mark_unavailable(&mut self, db: &Database, reason: String) {
+        self.set_health(db, ProviderHealth::Unavailable(reason)).await;
+    }
+
+    async fn set_health(&mut self, db: &Database, health: ProviderHealth) {
+        let changed = self.health.as_ref() != Some(&health);
+        if changed {
+            match (&self.health, &health) {
+                (_, ProviderHealth::Unavailable(reason)) => warn!(
+                    "Embeddings provider unavailable: {}. Files are still tracked; jobs stay pending.",
+                    reason
+                ),
+                (Some(ProviderHealth::Unavailable(_)), ProviderHealth::Available) => {
+                    info!("Embeddings provider reachable again, draining the queue")
+                }
+                (_, ProviderHealth::Available) => info!("Embeddings provider is reachable"),
+            }
+        } else {
+            debug!("Embeddings provider health unchanged: {:?}", health);
+        }
+
+        let recorded = health.clone();
+        if let Err(e) = db.call(move |db| db.transaction(|tx| record(tx, &recorded))).await {
+            warn!("Failed to record provider health: {}", e);
+        }
+        if changed {
+            let event = match &health {
+                ProviderHealth::Available => BakoEvent::ProviderAvailable,
+                ProviderHealth::Unavailable(reason) => BakoEvent::ProviderUnavailable {
+                    reason: reason.clone(),
+                },
+            };
+            let _ = self.events.send(event);
+        }
+        self.health = Some(health);
+    }
+}
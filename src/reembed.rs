@@ -0,0 +1,274 @@
+use tracing::{info, warn};
+
+use crate::db::Transaction;
+use crate::embeddings::EmbeddingModel;
+
+const ACTIVE_MODEL_KEY: &str = "active_embedding_model";
+const ACTIVE_DIMENSIONS_KEY: &str = "active_embedding_dimensions";
+const TARGET_MODEL_KEY: &str = "target_embedding_model";
+const TARGET_DIMENSIONS_KEY: &str = "target_embedding_dimensions";
+
+fn load(
+    tx: &Transaction,
+    model_key: &str,
+    dimensions_key: &str,
+) -> rusqlite::Result<Option<EmbeddingModel>> {
+    let model = tx.meta().get(model_key)?;
+    let dimensions = tx.meta().get(dimensions_key)?.and_then(|d| d.parse().ok());
+    Ok(model
+        .zip(dimensions)
+        .map(|(model, dimensions)| EmbeddingModel { model, dimensions }))
+}
+
+fn store(
+    tx: &Transaction,
+    model_key: &str,
+    dimensions_key: &str,
+    model: &EmbeddingModel,
+) -> rusqlite::Result<()> {
+    tx.meta().set(model_key, &model.model)?;
+    tx.meta().set(dimensions_key, &model.dimensions.to_string())
+}
+
+/// The model whose vectors search should use.
+pub fn active_model(tx: &Transaction) -> rusqlite::Result<Option<EmbeddingModel>> {
+    load(tx, ACTIVE_MODEL_KEY, ACTIVE_DIMENSIONS_KEY)
+}
+
+/// The model a `bako reembed` migration is moving to, if one is in progress.
+pub fn target_model(tx: &Transaction) -> rusqlite::Result<Option<EmbeddingModel>> {
+    load(tx, TARGET_MODEL_KEY, TARGET_DIMENSIONS_KEY)
+}
+
+/// Records the configured model as active on a fresh database, and warns when
+/// the configuration no longer matches the vectors search is using.
+pub fn check_embedding_config(tx: &Transaction, configured: &EmbeddingModel) -> rusqlite::Result<()> {
+    match active_model(tx)? {
+        None => store(tx, ACTIVE_MODEL_KEY, ACTIVE_DIMENSIONS_KEY, configured),
+        Some(active) if &active == configured => Ok(()),
+        Some(active) => {
+            if target_model(tx)?.as_ref() != Some(configured) {
+                warn!(
+                    "Configured embedding model {} differs from the indexed model {}. Run `bako reembed` to migrate.",
+                    configured, active
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+/// What `bako reembed` did.
+#[derive(Debug)]
+pub enum ReembedStart {
+    /// The index already uses the configured model.
+    UpToDate(EmbeddingModel),
+    /// A migration towards `target` is running with `queued` new jobs and
+    /// `open` jobs in total still to do.
+    Queued {
+        from: EmbeddingModel,
+        target: EmbeddingModel,
+        queued: usize,
+        open: usize,
+    },
+}
+
+/// Starts (or resumes) a migration to the configured model by queueing a
+/// `reembed` job for every file that lacks vectors from it. The running bako
+/// processes them in the background; search keeps using the active model's
+/// vectors until every job is done.
+pub fn start(tx: &Transaction, configured: EmbeddingModel) -> rusqlite::Result<ReembedStart> {
+    let active = match active_model(tx)? {
+        Some(active) => active,
+        None => {
+            store(tx, ACTIVE_MODEL_KEY, ACTIVE_DIMENSIONS_KEY, &configured)?;
+            return Ok(ReembedStart::UpToDate(configured));
+        }
+    };
+
+    if active == configured {
+        tx.meta().delete(TARGET_MODEL_KEY)?;
+        tx.meta().delete(TARGET_DIMENSIONS_KEY)?;
+        return Ok(ReembedStart::UpToDate(active));
+    }
+
+    store(tx, TARGET_MODEL_KEY, TARGET_DIMENSIONS_KEY, &configured)?;
+    let queued = tx
+        .jobs()
+        .insert_reembed_jobs(&configured.model, configured.dimensions)?;
+    let open = tx.jobs().count_open_reembed_jobs()?;
+    Ok(ReembedStart::Queued {
+        from: active,
+        target: configured,
+        queued,
+        open,
+    })
+}
+
+/// Once every `reembed` job has finished, makes the target model active and
+/// drops the old vectors. `current` is the model the queue is embedding with;
+/// nothing is switched if it no longer matches the migration target.
+pub fn complete_if_done(tx: &Transaction, current: &EmbeddingModel) -> rusqlite::Result<()> {
+    let Some(target) = target_model(tx)? else {
+        return Ok(());
+    };
+    if &target != current {
+        warn!(
+            "Re-embedding targets {} but the queue is embedding with {}; run `bako reembed` again",
+            target, current
+        );
+        return Ok(());
+    }
+    if tx.jobs().count_open_reembed_jobs()? > 0 {
+        return Ok(());
+    }
+
+    let removed = tx
+        .embeddings()
+        .delete_other_models(&target.model, target.dimensions)?;
+    store(tx, ACTIVE_MODEL_KEY, ACTIVE_DIMENSIONS_KEY, &target)?;
+    tx.meta().delete(TARGET_MODEL_KEY)?;
+    tx.meta().delete(TARGET_DIMENSIONS_KEY)?;
+    info!(
+        "Re-embedding complete: search now uses {} ({} old vectors removed)",
+        target, removed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::job_repo::JobStatus;
+    use crate::db::testing::temp_db;
+    use crate::file::FileSource;
+
+    fn model(name: &str, dimensions: usize) -> EmbeddingModel {
+        EmbeddingModel {
+            model: name.to_string(),
+            dimensions,
+        }
+    }
+
+    /// Adds a file embedded with `embedded_with`.
+    fn add_file(tx: &Transaction, path: &str, embedded_with: &EmbeddingModel) -> String {
+        let file = tx
+            .files()
+            .upsert_file(path, "text/plain", path, 1, FileSource::Watched, None)
+            .unwrap();
+        let chunk_ids = tx
+            .chunks()
+            .replace_chunks(&file.id, &[("text".to_string(), 1)])
+            .unwrap();
+        embed(tx, &file.id, &chunk_ids[0], embedded_with);
+        file.id
+    }
+
+    fn embed(tx: &Transaction, file_id: &str, chunk_id: &str, model: &EmbeddingModel) {
+        let vector = serde_json::to_string(&vec![0.5; model.dimensions]).unwrap();
+        tx.embeddings()
+            .insert_embedding(file_id, chunk_id, &model.model, model.dimensions, &vector)
+            .unwrap();
+    }
+
+    fn finish_reembed_jobs(tx: &Transaction, to: &EmbeddingModel) {
+        for job in tx.jobs().get_jobs(JobStatus::Pending, None).unwrap() {
+            let chunk = tx.chunks().get_chunks(&job.file_id).unwrap().remove(0);
+            embed(tx, &job.file_id, &chunk.id, to);
+            tx.jobs()
+                .update_job_batch(&[job.id], JobStatus::Completed, None)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn a_fresh_index_adopts_the_configured_model() {
+        let db = temp_db();
+        let configured = model("small", 4);
+        db.transaction(|tx| {
+            check_embedding_config(tx, &configured)?;
+            assert_eq!(active_model(tx)?, Some(configured.clone()));
+            // A different configuration later doesn't change what is active.
+            check_embedding_config(tx, &model("large", 8))?;
+            assert_eq!(active_model(tx)?, Some(configured.clone()));
+            assert_eq!(target_model(tx)?, None);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn start_is_up_to_date_when_the_model_is_unchanged() {
+        let db = temp_db();
+        let small = model("small", 4);
+        db.transaction(|tx| {
+            check_embedding_config(tx, &small)?;
+            add_file(tx, "/tmp/a.txt", &small);
+            assert!(matches!(start(tx, small.clone())?, ReembedStart::UpToDate(m) if m == small));
+            assert_eq!(tx.jobs().count_open_reembed_jobs()?, 0);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn start_queues_each_file_once_and_resumes() {
+        let db = temp_db();
+        let (small, large) = (model("small", 4), model("large", 8));
+        db.transaction(|tx| {
+            check_embedding_config(tx, &small)?;
+            add_file(tx, "/tmp/a.txt", &small);
+            add_file(tx, "/tmp/b.txt", &small);
+            add_file(tx, "/tmp/c.txt", &large);
+
+            let ReembedStart::Queued {
+                from,
+                target,
+                queued,
+                open,
+            } = start(tx, large.clone())?
+            else {
+                panic!("expected jobs to be queued");
+            };
+            assert_eq!((from, target), (small.clone(), large.clone()));
+            assert_eq!((queued, open), (2, 2));
+            assert_eq!(target_model(tx)?, Some(large.clone()));
+
+            // Running it again queues nothing new.
+            let ReembedStart::Queued { queued, open, .. } = start(tx, large.clone())? else {
+                panic!("expected the migration to resume");
+            };
+            assert_eq!((queued, open), (0, 2));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn switches_to_the_target_once_every_job_is_done() {
+        let db = temp_db();
+        let (small, large) = (model("small", 4), model("large", 8));
+        db.transaction(|tx| {
+            check_embedding_config(tx, &small)?;
+            add_file(tx, "/tmp/a.txt", &small);
+            add_file(tx, "/tmp/b.txt", &small);
+            start(tx, large.clone())?;
+
+            complete_if_done(tx, &large)?;
+            assert_eq!(active_model(tx)?, Some(small.clone()));
+
+            finish_reembed_jobs(tx, &large);
+            // Embedding with some other model doesn't finish the migration.
+            complete_if_done(tx, &model("other", 4))?;
+            assert_eq!(active_model(tx)?, Some(small.clone()));
+
+            complete_if_done(tx, &large)?;
+            assert_eq!(active_model(tx)?, Some(large.clone()));
+            assert_eq!(target_model(tx)?, None);
+            assert!(tx.embeddings().get_for_model(&small.model, small.dimensions)?.is_empty());
+            assert_eq!(tx.embeddings().get_for_model(&large.model, large.dimensions)?.len(), 2);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use serde::Serialize;
+use crate::db::Database;
+use crate::embeddings::{Embedder, PreparedInput};
+use crate::error::Result;
+use crate::usage;
+
+/// A chunk of an indexed file that matched a query.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit {
+    /// The node the file was replicated from; `None` for files indexed here.
+    pub node_id: Option<String>,
+    pub file_id: String,
+    pub path: String,
+    /// Position of the matching chunk in the file, when the file is chunked.
+    pub chunk_index: Option<usize>,
+    /// Text of the matching chunk, when it is stored.
+    pub content: Option<String>,
+    /// Metadata the file was indexed with, for text indexed through the API.
+    pub metadata: Option<serde_json::Value>,
+    /// Cosine similarity between the query and the chunk, from -1 to 1.
+    pub score: f32,
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Embeds `query` and returns the `limit` most similar chunks among the
+/// vectors `embedder`'s model produced, best first. The query's token usage
+/// is recorded like any other request.
+pub async fn search(
+    db: &Database,
+    embedder: &Embedder,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchHit>> {
+    let query = match embedder.prepare(query) {
+        PreparedInput::Chunks(mut chunks) => chunks.swap_remove(0).0,
+        PreparedInput::Skipped(_) => return Ok(Vec::new()),
+    };
+
+    let mut outcome = embedder.embed_many(&[query]).await;
+    let query_vector = outcome.results.remove(0)?;
+
+    let provider = embedder.provider_name();
+    let model = embedder.model_info();
+    let stored = db
+        .call(move |db| {
+            db.transaction(|tx| {
+                for request in &outcome.usage {
+                    usage::record_query_usage(tx, provider, request)?;
+                }
+                tx.embeddings().get_for_model(&model.model, model.dimensions)
+            })
+        })
+        .await?;
+
+    let mut hits: Vec<SearchHit> = stored
+        .into_iter()
+        .filter_map(|embedding| {
+            let vector: Vec<f32> = serde_json::from_str(&embedding.embedding).ok()?;
+            Some(SearchHit {
+                score: cosine_similarity(&query_vector, &vector),
+                node_id: embedding.node_id,
+                file_id: embedding.file_id,
+                path: embedding.path,
+                chunk_index: embedding.chunk_index.map(|i| i as usize),
+                content: embedding.content,
+                metadata: embedding
+                    .metadata
+                    .and_then(|json| serde_json::from_str(&json).ok()),
+            })
+        })
+        .collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    Ok(hits)
+}
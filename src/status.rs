@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::db::embedding_repo::Coverage;
+use crate::db::file_repo::FileTypeCount;
+use crate::db::job_repo::JobCounts;
+use crate::db::{Database, DatabaseSize};
+use crate::embeddings;
+use crate::db::node_repo::NodeSummary;
+use crate::provider::{self, ProviderStatus};
+use crate::sync::{self, PushState};
+use crate::{indexer, reembed};
+
+/// How far back finished jobs are counted to estimate the queue's ETA.
+pub const THROUGHPUT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// A snapshot of the index for `bako status` and `GET /status`.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub node_id: String,
+    pub provider: String,
+    pub provider_status: Option<ProviderStatus>,
+    pub files: Vec<FileTypeCount>,
+    pub jobs: JobCounts,
+    /// Files whose vectors from the active model are stale or missing.
+    pub embeddings: Coverage,
+    /// When a file change was last recorded (UTC).
+    pub last_change_at: Option<String>,
+    /// When a job last finished (UTC).
+    pub last_job_at: Option<String>,
+    pub database: DatabaseSize,
+    /// Estimated time to work through the pending jobs at the recent rate;
+    /// `None` when the queue is empty or nothing finished recently.
+    pub eta_secs: Option<u64>,
+    /// Where pushing to `[sync] push_url` got to, if configured.
+    pub push_url: Option<String>,
+    pub push_state: Option<PushState>,
+    /// The peer mirrored from `[sync] pull_url`, if configured.
+    pub pull_url: Option<String>,
+    /// Nodes that pushed to this database or were mirrored into it.
+    pub nodes: Vec<NodeSummary>,
+}
+
+pub fn collect(db: &Database, config: &Config) -> rusqlite::Result<StatusReport> {
+    let provider = match embeddings::configured_model(config) {
+        Ok(model) => format!("{}, {}", config.embedding_provider, model),
+        Err(e) => format!("{} (misconfigured: {})", config.embedding_provider, e),
+    };
+    let database = db.size()?;
+
+    db.snapshot(|tx| {
+        let jobs = tx.jobs().count_by_status()?;
+        let remaining = jobs.pending + jobs.running;
+        let eta = match remaining {
+            0 => None,
+            _ => tx.jobs().recent_throughput(THROUGHPUT_WINDOW)?.eta(remaining),
+        };
+        let model = match reembed::active_model(tx)? {
+            Some(model) => Some(model),
+            None => embeddings::configured_model(config).ok(),
+        };
+        let coverage = match model {
+            Some(model) => tx.embeddings().coverage(&model.model, model.dimensions)?,
+            None => Coverage::default(),
+        };
+
+        Ok(StatusReport {
+            node_id: db.node_id().to_string(),
+            provider,
+            provider_status: provider::load_status(tx)?,
+            files: tx.files().count_by_type(&config.watch_directory)?,
+            jobs,
+            embeddings: coverage,
+            last_change_at: indexer::last_change_at(tx)?,
+            last_job_at: tx.jobs().last_finished_at()?,
+            database,
+            eta_secs: eta.map(|eta| eta.as_secs()),
+            push_url: config.sync.push_url.clone(),
+            push_state: sync::load_push_state(tx)?,
+            pull_url: config.sync.pull_url.clone(),
+            nodes: tx.nodes().summaries()?,
+        })
+    })
+}
+
+pub fn print(report: &StatusReport) {
+    println!("Node:            {}", report.node_id);
+    println!("Provider:        {}", report.provider);
+    match &report.provider_status {
+        Some(status) => {
+            let detail = status
+                .detail
+                .as_deref()
+                .map(|d| format!(" ({})", d))
+                .unwrap_or_default();
+            let checked = status
+                .checked_at
+                .as_deref()
+                .map(|t| format!(", checked {} UTC", t))
+                .unwrap_or_default();
+            println!("Provider health: {}{}{}", status.status, detail, checked);
+        }
+        None => println!("Provider health: unknown (bako has not run yet)"),
+    }
+    let tracked: usize = report.files.iter().map(|count| count.files).sum();
+    println!("Files:           {}", tracked);
+    // Counts come grouped by root, so each root's types are consecutive.
+    for group in report.files.chunk_by(|a, b| a.root == b.root) {
+        let types: Vec<String> = group
+            .iter()
+            .map(|count| format!("{} {}", count.files, count.file_type))
+            .collect();
+        println!(
+            "  {}: {}",
+            group[0].root.as_deref().unwrap_or("elsewhere"),
+            types.join(", ")
+        );
+    }
+    let jobs = &report.jobs;
+    println!(
+        "Jobs:            {} pending, {} running, {} failed, {} skipped, {} completed",
+        jobs.pending, jobs.running, jobs.failed, jobs.skipped, jobs.completed
+    );
+    println!(
+        "Embeddings:      {} stale, {} missing",
+        report.embeddings.stale, report.embeddings.missing
+    );
+    println!(
+        "Last change:     {}",
+        report
+            .last_change_at
+            .as_deref()
+            .map(|t| format!("{} UTC", t))
+            .unwrap_or_else(|| "never".to_string())
+    );
+    println!(
+        "Last job:        {}",
+        report
+            .last_job_at
+            .as_deref()
+            .map(|t| format!("{} UTC", t))
+            .unwrap_or_else(|| "never".to_string())
+    );
+    let backlog = jobs.pending + jobs.running;
+    match report.eta_secs {
+        _ if backlog == 0 => println!("Backlog:         caught up"),
+        Some(secs) => println!(
+            "Backlog:         {} jobs, about {} left",
+            backlog,
+            format_duration(Duration::from_secs(secs))
+        ),
+        None => println!("Backlog:         {} jobs, no recent progress to estimate from", backlog),
+    }
+    println!(
+        "Database:        {} ({} free)",
+        format_bytes(report.database.bytes),
+        format_bytes(report.database.free_bytes)
+    );
+    if let Some(url) = &report.push_url {
+        match &report.push_state {
+            Some(state) => println!(
+                "Sync:            pushing to {}, up to change {}{}",
+                url,
+                state.last_seq,
+                state
+                    .pushed_at
+                    .as_deref()
+                    .map(|t| format!(" as of {} UTC", t))
+                    .unwrap_or_default()
+            ),
+            None => println!("Sync:            pushing to {}, nothing pushed yet", url),
+        }
+    }
+    if let Some(url) = &report.pull_url {
+        println!("Sync:            mirroring {}", url);
+    }
+    for node in &report.nodes {
+        println!(
+            "Node {}: {} files, up to change {} (last synced {} UTC)",
+            node.node_id, node.files, node.last_seq, node.last_sync_at
+        );
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB)
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
+
+/// `duration` to the minute when over an hour, else to the second.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
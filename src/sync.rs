@@ -0,0 +1,438 @@
+//! Replication of a node's index to other bako instances.
+//!
+//! A node turns its change log into [`SyncBatch`]es: every file touched in a
+//! range of the log is sent as a snapshot (metadata, chunks and vectors), or
+//! as a tombstone if it has since been deleted. Batches are idempotent, so
+//! resending one after a failure is harmless. The receiver keeps, per node,
+//! the last sequence number it applied; that checkpoint is where the next
+//! push (node to aggregator) or pull (replica from peer) resumes.
+//!
+//! Received files live in the node tables, apart from the receiver's own
+//! files: they are searchable but never hashed, extracted or embedded
+//! locally.
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::SyncConfig;
+use crate::db::{Database, Transaction};
+use crate::error::{BakoError, Result};
+use crate::file::{File, FileSource};
+
+/// Bumped whenever the batch format changes incompatibly.
+pub const SYNC_FORMAT_VERSION: u32 = 1;
+
+const PUSHED_SEQ_KEY: &str = "sync_pushed_seq";
+const PUSHED_AT_KEY: &str = "sync_pushed_at";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncBatch {
+    pub version: u32,
+    pub node_id: String,
+    /// Sequence number of the last change log entry the batch covers.
+    pub last_seq: i64,
+    pub changes: Vec<SyncChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum SyncChange {
+    /// The file's current state, replacing whatever the receiver holds.
+    Upsert(SyncFile),
+    /// The file was removed from the node.
+    Delete { file_id: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncFile {
+    pub file_id: String,
+    pub path: String,
+    pub file_type: String,
+    pub hash: String,
+    pub size: i64,
+    pub source: FileSource,
+    pub metadata: Option<serde_json::Value>,
+    pub updated_at: String,
+    pub chunks: Vec<SyncChunk>,
+    pub embeddings: Vec<SyncEmbedding>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncChunk {
+    pub chunk_index: i64,
+    pub content: String,
+    pub token_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncEmbedding {
+    /// `None` for vectors written before files were chunked.
+    pub chunk_index: Option<i64>,
+    pub model: String,
+    pub dimensions: usize,
+    pub vector: Vec<f32>,
+}
+
+/// How far a receiver has applied a node's change log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub node_id: String,
+    pub last_seq: i64,
+}
+
+/// What one [`push`] sent.
+#[derive(Debug, Clone, Default)]
+pub struct PushReport {
+    pub batches: usize,
+    /// File snapshots and tombstones sent; a file changed between batches
+    /// counts once per batch.
+    pub files: usize,
+    pub last_seq: i64,
+}
+
+/// What one [`pull`] fetched.
+#[derive(Debug, Clone, Default)]
+pub struct PullReport {
+    pub node_id: String,
+    pub batches: usize,
+    /// File snapshots and tombstones applied.
+    pub files: usize,
+    pub last_seq: i64,
+}
+
+/// The last successful push, as recorded by [`push`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PushState {
+    pub last_seq: i64,
+    pub pushed_at: Option<String>,
+}
+
+pub fn load_push_state(tx: &Transaction) -> rusqlite::Result<Option<PushState>> {
+    let Some(last_seq) = tx.meta().get(PUSHED_SEQ_KEY)? else {
+        return Ok(None);
+    };
+    Ok(Some(PushState {
+        last_seq: last_seq.parse().unwrap_or(0),
+        pushed_at: tx.meta().get(PUSHED_AT_KEY)?,
+    }))
+}
+
+/// Builds the batch covering up to `limit` change log entries after `after`,
+/// or `None` when there are none.
+pub fn collect_batch(tx: &Transaction, node_id: &str, after: i64, limit: usize) -> rusqlite::Result<Option<SyncBatch>> {
+    let entries = tx.change_log().since(after, limit)?;
+    let Some(last) = entries.last() else {
+        return Ok(None);
+    };
+    let last_seq = last.seq;
+
+    let mut file_ids: Vec<String> = Vec::new();
+    for entry in entries {
+        if !file_ids.contains(&entry.file_id) {
+            file_ids.push(entry.file_id);
+        }
+    }
+
+    let mut changes = Vec::with_capacity(file_ids.len());
+    for file_id in file_ids {
+        match tx.files().find_file(&file_id)? {
+            Some(file) => changes.push(SyncChange::Upsert(snapshot_file(tx, file)?)),
+            None => changes.push(SyncChange::Delete { file_id }),
+        }
+    }
+
+    Ok(Some(SyncBatch {
+        version: SYNC_FORMAT_VERSION,
+        node_id: node_id.to_string(),
+        last_seq,
+        changes,
+    }))
+}
+
+/// A local file with its chunks and vectors from every model.
+pub fn snapshot_file(tx: &Transaction, file: File) -> rusqlite::Result<SyncFile> {
+    let chunks = tx
+        .chunks()
+        .get_chunks(&file.id)?
+        .into_iter()
+        .map(|chunk| SyncChunk {
+            chunk_index: chunk.chunk_index,
+            content: chunk.content,
+            token_count: chunk.token_count,
+        })
+        .collect();
+    let embeddings = tx
+        .embeddings()
+        .get_for_file(&file.id)?
+        .into_iter()
+        .map(|embedding| {
+            Ok(SyncEmbedding {
+                chunk_index: embedding.chunk_index,
+                model: embedding.model,
+                dimensions: embedding.dimensions,
+                vector: serde_json::from_str(&embedding.embedding)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
+            })
+        })
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(SyncFile {
+        file_id: file.id,
+        path: file.path,
+        file_type: file.file_type,
+        hash: file.hash,
+        size: file.size,
+        source: file.source,
+        metadata: file.metadata,
+        updated_at: file.updated_at,
+        chunks,
+        embeddings,
+    })
+}
+
+/// Stores a pushed batch in the node tables and moves the node's checkpoint
+/// to the end of the batch, which is returned.
+pub fn apply_batch(tx: &Transaction, batch: &SyncBatch) -> rusqlite::Result<i64> {
+    let nodes = tx.nodes();
+    nodes.ensure_node(&batch.node_id)?;
+    for change in &batch.changes {
+        match change {
+            SyncChange::Upsert(file) => nodes.upsert_file(&batch.node_id, file)?,
+            SyncChange::Delete { file_id } => nodes.delete_file(&batch.node_id, file_id)?,
+        }
+    }
+    nodes.set_checkpoint(&batch.node_id, batch.last_seq)?;
+    Ok(batch.last_seq)
+}
+
+fn endpoint(base: &str, path: &str) -> String {
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
+
+/// Sends `request` with this node's API token, if it has one, and decodes the
+/// JSON response.
+async fn send<T: serde::de::DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T> {
+    let request = match crate::http::api_token() {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+    let response = request
+        .send()
+        .await
+        .map_err(|e| BakoError::Sync(e.to_string()))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(BakoError::Sync(format!("{}: {}", status, body)));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| BakoError::Sync(format!("Invalid response: {}", e)))
+}
+
+/// Sends everything the aggregator at `config.push_url` hasn't applied yet,
+/// starting from the checkpoint it reports for this node.
+pub async fn push(db: &Database, client: &reqwest::Client, config: &SyncConfig) -> Result<PushReport> {
+    let url = config
+        .push_url
+        .as_deref()
+        .ok_or_else(|| BakoError::Config("[sync] push_url is not set".to_string()))?;
+    let node_id = db.node_id().to_string();
+
+    let checkpoint: Checkpoint =
+        send(client.get(endpoint(url, &format!("/sync/nodes/{}", node_id)))).await?;
+    let local_seq = db.call(|db| db.change_log()?.last_seq()).await?;
+    let mut after = checkpoint.last_seq;
+    if after > local_seq {
+        // The aggregator saw changes this database no longer has, e.g. after
+        // restoring an older copy: drop what it holds for this node, which
+        // may include files deleted since, and resend everything.
+        warn!(
+            "Aggregator is at change {} but this node only has {}; pushing from the start",
+            after, local_seq
+        );
+        let _: Checkpoint =
+            send(client.delete(endpoint(url, &format!("/sync/nodes/{}", node_id)))).await?;
+        after = 0;
+    }
+
+    let mut report = PushReport {
+        last_seq: after,
+        ..PushReport::default()
+    };
+    loop {
+        let limit = config.batch_size;
+        let id = node_id.clone();
+        let Some(batch) = db
+            .call(move |db| db.snapshot(|tx| collect_batch(tx, &id, after, limit)))
+            .await?
+        else {
+            break;
+        };
+        let files = batch.changes.len();
+        let acked: Checkpoint = send(client.post(endpoint(url, "/sync/push")).json(&batch)).await?;
+        if acked.last_seq <= after {
+            return Err(BakoError::Sync(format!(
+                "Aggregator acknowledged change {} after being sent up to {}",
+                acked.last_seq, batch.last_seq
+            )));
+        }
+        after = acked.last_seq;
+        db.call(move |db| {
+            db.transaction(|tx| {
+                tx.meta().set(PUSHED_SEQ_KEY, &after.to_string())?;
+                tx.meta().set_now(PUSHED_AT_KEY)
+            })
+        })
+        .await?;
+        report.batches += 1;
+        report.files += files;
+        report.last_seq = after;
+    }
+
+    if report.batches > 0 {
+        info!(
+            "Pushed {} file changes in {} batches to {} (up to change {})",
+            report.files, report.batches, url, report.last_seq
+        );
+    }
+    Ok(report)
+}
+
+/// Largest number of change log entries a peer serves in one batch.
+pub const MAX_PULL_BATCH: usize = 1000;
+
+/// Fetches the changes of the peer at `config.pull_url` since the last pull
+/// and applies them to the node tables, mirroring its index read-only.
+pub async fn pull(db: &Database, client: &reqwest::Client, config: &SyncConfig) -> Result<PullReport> {
+    let url = config
+        .pull_url
+        .as_deref()
+        .ok_or_else(|| BakoError::Config("[sync] pull_url is not set".to_string()))?;
+
+    let peer: Checkpoint = send(client.get(endpoint(url, "/sync/node"))).await?;
+    if peer.node_id == db.node_id() {
+        return Err(BakoError::Sync(format!("{} is this node", url)));
+    }
+    let peer_id = peer.node_id.clone();
+    let mut after = db.call(move |db| db.nodes()?.checkpoint(&peer_id)).await?;
+    if after > peer.last_seq {
+        // The peer's database is older than what was mirrored (e.g. restored
+        // from a backup): start over so files it no longer has disappear.
+        warn!(
+            "Mirrored up to change {} but {} is only at {}; mirroring from the start",
+            after, url, peer.last_seq
+        );
+        let peer_id = peer.node_id.clone();
+        db.call(move |db| db.nodes()?.delete_node(&peer_id)).await?;
+        after = 0;
+    }
+
+    let mut report = PullReport {
+        node_id: peer.node_id.clone(),
+        last_seq: after,
+        ..PullReport::default()
+    };
+    let limit = config.batch_size.min(MAX_PULL_BATCH);
+    loop {
+        let batch: SyncBatch = send(
+            client
+                .get(endpoint(url, "/sync/changes"))
+                .query(&[("after", after.to_string()), ("limit", limit.to_string())]),
+        )
+        .await?;
+        if batch.changes.is_empty() || batch.last_seq <= after {
+            break;
+        }
+        if batch.version != SYNC_FORMAT_VERSION || batch.node_id != peer.node_id {
+            return Err(BakoError::Sync(format!(
+                "{} sent a batch for node {} in format {}; expected node {} in format {}",
+                url, batch.node_id, batch.version, peer.node_id, SYNC_FORMAT_VERSION
+            )));
+        }
+        let files = batch.changes.len();
+        after = db
+            .call(move |db| db.transaction(|tx| apply_batch(tx, &batch)))
+            .await?;
+        report.batches += 1;
+        report.files += files;
+        report.last_seq = after;
+    }
+
+    if report.batches > 0 {
+        info!(
+            "Pulled {} file changes in {} batches from {} (node {}, up to change {})",
+            report.files, report.batches, url, report.node_id, report.last_seq
+        );
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::temp_db;
+
+    fn node_file_count(db: &Database) -> usize {
+        db.nodes().unwrap()
+            .summaries()
+            .unwrap()
+            .iter()
+            .map(|node| node.files)
+            .sum()
+    }
+
+    #[test]
+    fn batches_replicate_files_and_tombstones() {
+        let node = temp_db();
+        let aggregator = temp_db();
+        let file = node
+            .transaction(|tx| {
+                let file = tx.files().upsert_file(
+                    "/notes/a.md",
+                    "text/plain",
+                    "h",
+                    5,
+                    FileSource::Watched,
+                    None,
+                )?;
+                let chunk_ids = tx.chunks().replace_chunks(&file.id, &[("hello".to_string(), 1)])?;
+                tx.embeddings()
+                    .insert_embedding(&file.id, &chunk_ids[0], "m", 2, "[0.5,1.0]")?;
+                Ok(file)
+            })
+            .unwrap();
+
+        let batch = node
+            .snapshot(|tx| collect_batch(tx, node.node_id(), 0, 100))
+            .unwrap()
+            .unwrap();
+        let [SyncChange::Upsert(synced)] = batch.changes.as_slice() else {
+            panic!("expected one upsert, got {:?}", batch.changes);
+        };
+        assert_eq!(synced.file_id, file.id);
+        assert_eq!(synced.chunks.len(), 1);
+        assert_eq!(synced.embeddings[0].vector, vec![0.5, 1.0]);
+
+        let last_seq = aggregator.transaction(|tx| apply_batch(tx, &batch)).unwrap();
+        assert_eq!(aggregator.nodes().unwrap().checkpoint(node.node_id()).unwrap(), last_seq);
+        assert_eq!(node_file_count(&aggregator), 1);
+
+        node.files().unwrap().delete_file("/notes/a.md").unwrap();
+        let batch = node
+            .snapshot(|tx| collect_batch(tx, node.node_id(), last_seq, 100))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            batch.changes.as_slice(),
+            [SyncChange::Delete { file_id }] if *file_id == file.id
+        ));
+        aggregator.transaction(|tx| apply_batch(tx, &batch)).unwrap();
+        assert_eq!(node_file_count(&aggregator), 0);
+
+        assert!(node
+            .snapshot(|tx| collect_batch(tx, node.node_id(), batch.last_seq, 100))
+            .unwrap()
+            .is_none());
+    }
+}
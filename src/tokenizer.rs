@@ -0,0 +1,212 @@
+#[cfg(feature = "local-embeddings")]
+use std::sync::Arc;
+
+use tiktoken_rs::CoreBPE;
+
+use crate::error::{BakoError, Result};
+
+/// Counts and splits text the same way the embedding model will, so inputs can
+/// be kept under the model's context limit before they are sent.
+pub enum Tokenizer {
+    /// A tiktoken BPE encoding such as `cl100k_base`.
+    Bpe(&'static CoreBPE),
+    /// Roughly four bytes per token, for models without a known encoding.
+    Approximate,
+    /// The `tokenizer.json` shipped with a local model.
+    #[cfg(feature = "local-embeddings")]
+    HuggingFace(Arc<tokenizers::Tokenizer>),
+}
+
+const APPROXIMATE_BYTES_PER_TOKEN: usize = 4;
+
+impl Tokenizer {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "cl100k_base" => Ok(Tokenizer::Bpe(tiktoken_rs::cl100k_base_singleton())),
+            "o200k_base" => Ok(Tokenizer::Bpe(tiktoken_rs::o200k_base_singleton())),
+            "approximate" => Ok(Tokenizer::Approximate),
+            _ => Err(BakoError::Config(format!("Unknown tokenizer: {}", name))),
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            Tokenizer::Bpe(bpe) => bpe.encode_ordinary(text).len(),
+            Tokenizer::Approximate => text.len().div_ceil(APPROXIMATE_BYTES_PER_TOKEN),
+            #[cfg(feature = "local-embeddings")]
+            Tokenizer::HuggingFace(tokenizer) => tokenizer.encode(text, false).map_or_else(
+                |_| text.len().div_ceil(APPROXIMATE_BYTES_PER_TOKEN),
+                |e| e.len(),
+            ),
+        }
+    }
+
+    /// Splits `text` into consecutive pieces of at most `max_tokens` tokens each.
+    pub fn split(&self, text: &str, max_tokens: usize) -> Vec<String> {
+        let max_tokens = max_tokens.max(1);
+        match self {
+            Tokenizer::Bpe(bpe) => split_bpe(bpe, text, max_tokens),
+            Tokenizer::Approximate => {
+                split_by_bytes(text, max_tokens * APPROXIMATE_BYTES_PER_TOKEN)
+            }
+            #[cfg(feature = "local-embeddings")]
+            Tokenizer::HuggingFace(tokenizer) => split_by_offsets(tokenizer, text, max_tokens),
+        }
+    }
+
+    /// Returns the longest prefix of `text` that fits in `max_tokens` tokens.
+    pub fn truncate(&self, text: &str, max_tokens: usize) -> String {
+        self.split(text, max_tokens)
+            .into_iter()
+            .next()
+            .unwrap_or_default()
+    }
+}
+
+fn split_bpe(bpe: &CoreBPE, text: &str, max_tokens: usize) -> Vec<String> {
+    let tokens = bpe.encode_ordinary(text);
+    let mut pieces = Vec::new();
+    let mut start = 0;
+
+    while start < tokens.len() {
+        let limit = (start + max_tokens).min(tokens.len());
+        let decode = |end: usize| {
+            String::from_utf8(bpe.decode_bytes(&tokens[start..end]).unwrap_or_default()).ok()
+        };
+        // A token boundary can fall inside a multi-byte character; back off
+        // until the piece decodes cleanly so nothing is mangled. A character
+        // spread over more tokens than the limit is kept whole instead.
+        let (piece, end) = (start + 1..=limit)
+            .rev()
+            .chain(limit + 1..=tokens.len())
+            .find_map(|end| decode(end).map(|piece| (piece, end)))
+            .unwrap_or_else(|| {
+                let bytes = bpe.decode_bytes(&tokens[start..]).unwrap_or_default();
+                (String::from_utf8_lossy(&bytes).into_owned(), tokens.len())
+            });
+        pieces.push(piece);
+        start = end;
+    }
+
+    pieces
+}
+
+/// Cuts `text` at the byte offset of every `max_tokens`-th token, so each
+/// piece keeps the original text (case, whitespace) rather than a re-decoding.
+#[cfg(feature = "local-embeddings")]
+fn split_by_offsets(
+    tokenizer: &tokenizers::Tokenizer,
+    text: &str,
+    max_tokens: usize,
+) -> Vec<String> {
+    let encoding = match tokenizer.encode(text, false) {
+        Ok(encoding) => encoding,
+        Err(_) => return split_by_bytes(text, max_tokens * APPROXIMATE_BYTES_PER_TOKEN),
+    };
+
+    let mut cuts: Vec<usize> = encoding
+        .get_offsets()
+        .iter()
+        .step_by(max_tokens)
+        .skip(1)
+        .map(|&(start, _)| start)
+        .filter(|&start| start > 0 && start < text.len() && text.is_char_boundary(start))
+        .collect();
+    cuts.dedup();
+
+    let mut pieces = Vec::with_capacity(cuts.len() + 1);
+    let mut start = 0;
+    for cut in cuts {
+        pieces.push(text[start..cut].to_string());
+        start = cut;
+    }
+    pieces.push(text[start..].to_string());
+    pieces
+}
+
+fn split_by_bytes(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let mut end = max_bytes.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece.to_string());
+        rest = tail;
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenizers() -> Vec<(&'static str, Tokenizer)> {
+        vec![
+            ("cl100k_base", Tokenizer::from_name("cl100k_base").unwrap()),
+            ("approximate", Tokenizer::Approximate),
+        ]
+    }
+
+    const TEXTS: [&str; 3] = [
+        "The quick brown fox jumps over the lazy dog. ",
+        "Grüße aus Köln — naïve café, 東京タワー、ラーメン 🍜🍣. ",
+        "🦀🦀🦀🦀🦀🦀🦀🦀",
+    ];
+
+    #[test]
+    fn text_at_the_limit_is_kept_whole() {
+        for (name, tokenizer) in tokenizers() {
+            for text in TEXTS {
+                let limit = tokenizer.count(text);
+                assert_eq!(tokenizer.split(text, limit), vec![text], "{}", name);
+                assert_eq!(tokenizer.truncate(text, limit), text, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn split_pieces_fit_the_limit_and_rejoin_to_the_text() {
+        for (name, tokenizer) in tokenizers() {
+            for text in TEXTS {
+                let text = text.repeat(20);
+                for limit in [1, 7, 50] {
+                    let pieces = tokenizer.split(&text, limit);
+                    assert!(pieces.len() > 1, "{} {}", name, limit);
+                    assert_eq!(pieces.concat(), text, "{} {}", name, limit);
+                    for piece in &pieces {
+                        assert!(!piece.is_empty(), "{} {}", name, limit);
+                        // A character spread over several tokens is never cut,
+                        // so a limit of one can be exceeded.
+                        if limit > 1 {
+                            let count = tokenizer.count(piece);
+                            assert!(count <= limit, "{} {}: {:?}", name, limit, piece);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncation_keeps_a_prefix_within_the_limit() {
+        for (name, tokenizer) in tokenizers() {
+            for text in TEXTS {
+                let text = text.repeat(20);
+                for limit in [7, 50] {
+                    let truncated = tokenizer.truncate(&text, limit);
+                    assert!(!truncated.is_empty(), "{} {}", name, limit);
+                    assert!(text.starts_with(&truncated), "{} {}", name, limit);
+                    assert!(tokenizer.count(&truncated) <= limit, "{} {}", name, limit);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use crate::db::Transaction;
+use crate::db::usage_repo::{NewUsage, UsageGrouping, UsageSummary};
+use crate::embeddings::RequestUsage;
+
+/// The job an embeddings input belongs to, and its local token count.
+pub struct InputOwner<'a> {
+    pub job_id: &'a str,
+    pub root: Option<&'a str>,
+    pub tokens: usize,
+}
+
+/// Splits `total` across `weights` proportionally. Rounding leftovers go to the
+/// last part so the parts always add up to `total`.
+pub fn apportion(total: usize, weights: &[usize]) -> Vec<usize> {
+    let weight_sum: usize = weights.iter().sum();
+    if weights.is_empty() {
+        return Vec::new();
+    }
+    if weight_sum == 0 {
+        let mut parts = vec![0; weights.len()];
+        parts[weights.len() - 1] = total;
+        return parts;
+    }
+
+    let mut parts: Vec<usize> = weights
+        .iter()
+        .map(|&w| (total as u128 * w as u128 / weight_sum as u128) as usize)
+        .collect();
+    let assigned: usize = parts.iter().sum();
+    parts[weights.len() - 1] += total - assigned;
+    parts
+}
+
+/// Persists one request's usage, attributing its tokens to the jobs whose
+/// inputs it carried in proportion to their token counts. `owners` holds one
+/// entry per input of the request; a job's inputs are contiguous.
+pub fn record_request_usage(
+    tx: &Transaction,
+    provider: &str,
+    usage: &RequestUsage,
+    owners: &[InputOwner],
+) -> rusqlite::Result<()> {
+    let mut jobs: Vec<(&str, Option<&str>, usize)> = Vec::new();
+    for owner in owners {
+        match jobs.last_mut() {
+            Some((job_id, _, tokens)) if *job_id == owner.job_id => *tokens += owner.tokens,
+            _ => jobs.push((owner.job_id, owner.root, owner.tokens)),
+        }
+    }
+
+    let weights: Vec<usize> = jobs.iter().map(|(_, _, tokens)| *tokens).collect();
+    let prompt_parts = apportion(usage.prompt_tokens, &weights);
+    let total_parts = apportion(usage.total_tokens, &weights);
+    let request_id = uuid::Uuid::new_v4().to_string();
+
+    for (i, (job_id, root, _)) in jobs.iter().enumerate() {
+        tx.usage().insert_usage(&NewUsage {
+            request_id: &request_id,
+            provider,
+            model: &usage.model,
+            prompt_tokens: prompt_parts[i] as i64,
+            total_tokens: total_parts[i] as i64,
+            job_id: Some(job_id),
+            root: *root,
+        })?;
+    }
+    Ok(())
+}
+
+/// Persists the usage of a request made for a search query rather than a job.
+pub fn record_query_usage(tx: &Transaction, provider: &str, usage: &RequestUsage) -> rusqlite::Result<()> {
+    tx.usage().insert_usage(&NewUsage {
+        request_id: &uuid::Uuid::new_v4().to_string(),
+        provider,
+        model: &usage.model,
+        prompt_tokens: usage.prompt_tokens as i64,
+        total_tokens: usage.total_tokens as i64,
+        job_id: None,
+        root: None,
+    })
+}
+
+fn cost(summary: &UsageSummary, prices: &HashMap<String, f64>) -> Option<f64> {
+    prices
+        .get(&summary.model)
+        .map(|price| summary.total_tokens as f64 / 1_000_000.0 * price)
+}
+
+/// Prints a usage table, one line per group and model, followed by totals.
+pub fn print_report(grouping: UsageGrouping, rows: &[UsageSummary], prices: &HashMap<String, f64>) {
+    let heading = match grouping {
+        UsageGrouping::Day => "DAY",
+        UsageGrouping::Model => "MODEL",
+        UsageGrouping::Root => "ROOT",
+    };
+
+    if rows.is_empty() {
+        println!("No usage recorded.");
+        return;
+    }
+
+    println!(
+        "{:<40} {:<28} {:>9} {:>14} {:>14} {:>11}",
+        heading, "MODEL", "REQUESTS", "PROMPT TOKENS", "TOTAL TOKENS", "COST (USD)"
+    );
+
+    let mut requests = 0;
+    let mut prompt_tokens = 0;
+    let mut total_tokens = 0;
+    let mut total_cost = Some(0.0);
+    for row in rows {
+        let row_cost = cost(row, prices);
+        println!(
+            "{:<40} {:<28} {:>9} {:>14} {:>14} {:>11}",
+            row.group,
+            row.model,
+            row.requests,
+            row.prompt_tokens,
+            row.total_tokens,
+            row_cost.map_or("-".to_string(), |c| format!("{:.6}", c)),
+        );
+        requests += row.requests;
+        prompt_tokens += row.prompt_tokens;
+        total_tokens += row.total_tokens;
+        total_cost = total_cost.zip(row_cost).map(|(a, b)| a + b);
+    }
+
+    println!(
+        "{:<40} {:<28} {:>9} {:>14} {:>14} {:>11}",
+        "TOTAL",
+        "",
+        requests,
+        prompt_tokens,
+        total_tokens,
+        total_cost.map_or("-".to_string(), |c| format!("{:.6}", c)),
+    );
+}
@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use blake3::Hasher;
 use tokio::fs;
 use tokio::io::{self, AsyncReadExt};
@@ -24,3 +26,26 @@ pub async fn hash_file(path: &str) -> io::Result<String> {
 
     Ok(hasher.finalize().to_hex().to_string())
 }
+
+pub fn hash_text(text: &str) -> String {
+    blake3::hash(text.as_bytes()).to_hex().to_string()
+}
+
+/// Every regular file under `dir`, recursively. Symlinks are not followed.
+pub async fn list_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}